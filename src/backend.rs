@@ -0,0 +1,370 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "std")]
+use std::{
+	iter,
+	panic,
+	thread::{self, ScopedJoinHandle},
+};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "std")]
+use crate::config;
+#[cfg(feature = "rayon")]
+use crate::inherit::InheritExt;
+use crate::{FromThreadedIterator, Options};
+
+/// Runs the parallel branch of [`threaded_map`] and [`threaded_mutate`].
+///
+/// Executors are only handed inputs that reached the threshold, small inputs
+/// are always processed sequentially on the calling thread.
+///
+/// [`threaded_map`]: crate::threaded_map
+/// [`threaded_mutate`]: crate::threaded_mutate
+pub trait Executor {
+	/// Runs `map` on each element of `src`, preserving order
+	fn map<T, F, U, R>(&self, src: Vec<T>, options: &Options<'_>, map: F) -> R
+	where
+		F: Fn(T) -> U + Send + Sync,
+		R: FromThreadedIterator<U>,
+		T: Send,
+		U: Send;
+
+	/// Runs `map` on each element of `src` in place
+	fn mutate<T, F>(&self, src: &mut [T], options: &Options<'_>, map: F)
+	where
+		F: Fn(&mut T) + Send + Sync,
+		T: Send;
+}
+
+/// Executor running on rayon's thread pools.
+///
+/// Honors every field of [`Options`].
+#[cfg(feature = "rayon")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RayonExecutor;
+
+#[cfg(feature = "rayon")]
+impl Executor for RayonExecutor {
+	fn map<T, F, U, R>(&self, src: Vec<T>, options: &Options<'_>, map: F) -> R
+	where
+		F: Fn(T) -> U + Send + Sync,
+		R: FromThreadedIterator<U>,
+		T: Send,
+		U: Send,
+	{
+		options.collect(
+			src.into_par_iter()
+				.with_min_len(options.min_len)
+				.with_max_len(options.max_len)
+				.inherit()
+				.map(map),
+		)
+	}
+
+	fn mutate<T, F>(&self, src: &mut [T], options: &Options<'_>, map: F)
+	where
+		F: Fn(&mut T) + Send + Sync,
+		T: Send,
+	{
+		options.install(|| {
+			src.par_iter_mut()
+				.with_min_len(options.min_len)
+				.with_max_len(options.max_len)
+				.inherit()
+				.for_each(map)
+		})
+	}
+}
+
+/// Executor spawning one [`std::thread::scope`] thread per available core, or
+/// per [`Config::threads`](crate::Config::threads) if set.
+///
+/// Each thread gets one contiguous chunk of at least
+/// [`min_len`](Options::min_len) elements, the calling thread processes the
+/// first one. [`max_len`](Options::max_len) and [`pool`](Options::pool) are
+/// ignored.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadsExecutor;
+
+#[cfg(feature = "std")]
+impl ThreadsExecutor {
+	fn chunk_len(len: usize, options: &Options<'_>) -> usize {
+		len.div_ceil(config::num_threads())
+			.max(options.min_len)
+			.max(1)
+	}
+
+	fn join<T>(handle: ScopedJoinHandle<'_, T>) -> T {
+		handle
+			.join()
+			.unwrap_or_else(|payload| panic::resume_unwind(payload))
+	}
+}
+
+#[cfg(feature = "std")]
+impl Executor for ThreadsExecutor {
+	fn map<T, F, U, R>(&self, mut src: Vec<T>, options: &Options<'_>, map: F) -> R
+	where
+		F: Fn(T) -> U + Send + Sync,
+		R: FromThreadedIterator<U>,
+		T: Send,
+		U: Send,
+	{
+		let chunk_len = Self::chunk_len(src.len(), options);
+		let mut chunks = Vec::new();
+		while src.len() > chunk_len {
+			chunks.push(src.split_off(src.len() - chunk_len));
+		}
+		chunks.reverse();
+
+		let map = &map;
+		let inherited = config::current_override();
+		let outputs: Vec<Vec<U>> = thread::scope(|s| {
+			let handles: Vec<_> = chunks
+				.into_iter()
+				.map(|chunk| {
+					s.spawn(move || {
+						config::inherit(inherited, || chunk.into_iter().map(map).collect())
+					})
+				})
+				.collect();
+			let first = src.into_iter().map(map).collect();
+			iter::once(first)
+				.chain(handles.into_iter().map(Self::join))
+				.collect()
+		});
+		outputs.into_iter().flatten().collect()
+	}
+
+	fn mutate<T, F>(&self, src: &mut [T], options: &Options<'_>, map: F)
+	where
+		F: Fn(&mut T) + Send + Sync,
+		T: Send,
+	{
+		let chunk_len = Self::chunk_len(src.len(), options);
+		let map = &map;
+		let inherited = config::current_override();
+		thread::scope(|s| {
+			let mut chunks = src.chunks_mut(chunk_len);
+			let first = chunks.next();
+			let handles: Vec<_> = chunks
+				.map(|chunk| {
+					s.spawn(move || config::inherit(inherited, || chunk.iter_mut().for_each(map)))
+				})
+				.collect();
+			first.into_iter().flatten().for_each(map);
+			handles.into_iter().for_each(Self::join);
+		})
+	}
+}
+
+/// Executor processing every element on the calling thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequentialExecutor;
+
+impl Executor for SequentialExecutor {
+	fn map<T, F, U, R>(&self, src: Vec<T>, _options: &Options<'_>, map: F) -> R
+	where
+		F: Fn(T) -> U + Send + Sync,
+		R: FromThreadedIterator<U>,
+		T: Send,
+		U: Send,
+	{
+		src.into_iter().map(map).collect()
+	}
+
+	fn mutate<T, F>(&self, src: &mut [T], _options: &Options<'_>, map: F)
+	where
+		F: Fn(&mut T) + Send + Sync,
+		T: Send,
+	{
+		src.iter_mut().for_each(map)
+	}
+}
+
+/// Built-in executors, selectable per call through [`Options::backend`] or
+/// globally through [`set_default_backend`].
+///
+/// Only [`threaded_map`] and [`threaded_mutate`] along with their variants
+/// taking [`Options`] go through the selected backend. Other functions of this
+/// crate run on rayon when the `Rayon` backend is selected and sequentially
+/// otherwise.
+///
+/// The default is `Rayon`, or `Threads` when built without the `rayon`
+/// feature, or `Sequential` when built without the `std` feature either.
+///
+/// Which variants exist depends on the enabled features, and enabling a
+/// feature anywhere in the dependency graph adds them, so matches on this
+/// enum need a wildcard arm.
+///
+/// [`threaded_map`]: crate::threaded_map
+/// [`threaded_mutate`]: crate::threaded_mutate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum Backend {
+	/// See [`RayonExecutor`]
+	#[cfg(feature = "rayon")]
+	#[default]
+	Rayon,
+	/// See [`ThreadsExecutor`]
+	#[cfg(feature = "std")]
+	#[cfg_attr(not(feature = "rayon"), default)]
+	Threads,
+	/// See [`SequentialExecutor`]
+	#[cfg_attr(not(feature = "std"), default)]
+	Sequential,
+}
+
+impl Backend {
+	#[cfg(feature = "rayon")]
+	pub(crate) const DEFAULT: Self = Self::Rayon;
+	#[cfg(all(feature = "std", not(feature = "rayon")))]
+	pub(crate) const DEFAULT: Self = Self::Threads;
+	#[cfg(not(feature = "std"))]
+	pub(crate) const DEFAULT: Self = Self::Sequential;
+
+	fn from_u8(value: u8) -> Self {
+		match value {
+			#[cfg(feature = "rayon")]
+			x if x == Self::Rayon as u8 => Self::Rayon,
+			#[cfg(feature = "std")]
+			x if x == Self::Threads as u8 => Self::Threads,
+			_ => Self::Sequential,
+		}
+	}
+}
+
+impl Executor for Backend {
+	fn map<T, F, U, R>(&self, src: Vec<T>, options: &Options<'_>, map: F) -> R
+	where
+		F: Fn(T) -> U + Send + Sync,
+		R: FromThreadedIterator<U>,
+		T: Send,
+		U: Send,
+	{
+		match self {
+			#[cfg(feature = "rayon")]
+			Self::Rayon => RayonExecutor.map(src, options, map),
+			#[cfg(feature = "std")]
+			Self::Threads => ThreadsExecutor.map(src, options, map),
+			Self::Sequential => SequentialExecutor.map(src, options, map),
+		}
+	}
+
+	fn mutate<T, F>(&self, src: &mut [T], options: &Options<'_>, map: F)
+	where
+		F: Fn(&mut T) + Send + Sync,
+		T: Send,
+	{
+		match self {
+			#[cfg(feature = "rayon")]
+			Self::Rayon => RayonExecutor.mutate(src, options, map),
+			#[cfg(feature = "std")]
+			Self::Threads => ThreadsExecutor.mutate(src, options, map),
+			Self::Sequential => SequentialExecutor.mutate(src, options, map),
+		}
+	}
+}
+
+/// Marks [`DEFAULT_BACKEND`] as never set, deferring to the configuration
+const UNSET: u8 = u8::MAX;
+
+static DEFAULT_BACKEND: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Returns the backend used by [`Options::default`], which is
+/// [`Config::backend`](crate::Config::backend) of the effective
+/// [`config`](crate::config()).
+pub fn default_backend() -> Backend {
+	crate::config().backend
+}
+
+/// Backend stored by [`set_default_backend`], if it was ever called
+pub(crate) fn stored_backend() -> Option<Backend> {
+	match DEFAULT_BACKEND.load(Ordering::Relaxed) {
+		UNSET => None,
+		value => Some(Backend::from_u8(value)),
+	}
+}
+
+/// Changes the backend used by [`Options::default`] for the whole process.
+///
+/// This replaces [`Config::backend`](crate::Config::backend) of the loaded
+/// configuration, so [`config`](crate::config()) reports it as well. A backend
+/// set with `scope` or `override_config` still takes precedence.
+///
+/// # Example
+/// ```
+/// # use vemcap::{config, default_backend, set_default_backend, threaded_map, Backend};
+/// set_default_backend(Backend::Sequential);
+/// assert_eq!(default_backend(), Backend::Sequential);
+/// assert_eq!(config().backend, Backend::Sequential);
+/// let output: Vec<_> = threaded_map((0..1000).collect(), |x: u32| x + 1);
+/// assert_eq!(output, (1..1001).collect::<Vec<_>>());
+/// ```
+pub fn set_default_backend(backend: Backend) {
+	DEFAULT_BACKEND.store(backend as u8, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(feature = "std")]
+	mod threads_executor {
+		use std::{panic, thread};
+
+		use super::super::{Executor, ThreadsExecutor};
+		use crate::Options;
+
+		#[test]
+		fn map() {
+			let input = (0..1024u32).collect();
+			let expected: Vec<_> = (0..1024u32).map(|x| x.pow(2)).collect();
+			let output: Vec<_> = ThreadsExecutor.map(input, &Options::default(), |x| x.pow(2));
+			assert_eq!(output, expected);
+		}
+
+		#[test]
+		fn mutate() {
+			let mut data = vec![None; 1024];
+			ThreadsExecutor.mutate(&mut data, &Options::default(), |x| {
+				*x = Some(thread::current().id())
+			});
+			let caller = thread::current().id();
+			assert_eq!(data[0], Some(caller));
+			assert!(data.into_iter().all(|x| x.is_some()));
+		}
+
+		#[test]
+		fn panic_payload() {
+			let result = panic::catch_unwind(|| {
+				let mut data: Vec<_> = (0..1024).collect();
+				ThreadsExecutor.mutate(&mut data, &Options::default(), |x| {
+					assert_ne!(*x, 1023, "last element");
+				});
+			});
+			let payload = result.unwrap_err();
+			let message = payload.downcast_ref::<String>().unwrap();
+			assert!(message.contains("last element"));
+		}
+	}
+
+	mod sequential_executor {
+		use std::thread;
+
+		use super::super::{Executor, SequentialExecutor};
+		use crate::Options;
+
+		#[test]
+		fn caller_thread() {
+			let caller = thread::current().id();
+			let input = vec![(); 1024];
+			let output: Vec<_> =
+				SequentialExecutor.map(input, &Options::default(), |()| thread::current().id());
+			assert!(output.into_iter().all(|id| id == caller));
+		}
+	}
+}
@@ -0,0 +1,142 @@
+use std::{
+	collections::HashMap,
+	hint::black_box,
+	panic::Location,
+	sync::{Mutex, OnceLock},
+	thread,
+	time::{Duration, Instant},
+};
+
+use crate::{Backend, Options};
+
+/// Number of elements timed sequentially before deciding how to split work
+pub(crate) const SAMPLE_LEN: usize = 16;
+
+/// How many times the spawn overhead a single parallel job should cost
+const JOB_COST_FACTOR: u128 = 4;
+
+type Cache = Mutex<HashMap<(&'static Location<'static>, Backend), Calibration>>;
+
+/// Cutover learned from timing a sample of elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Calibration {
+	threshold: usize,
+	min_len: usize,
+}
+
+impl Calibration {
+	fn new(sample_len: usize, elapsed: Duration, overhead: Duration) -> Self {
+		let job_cost = overhead.as_nanos().saturating_mul(JOB_COST_FACTOR);
+		let min_len = job_cost.saturating_mul(sample_len as u128) / elapsed.as_nanos().max(1);
+		let min_len = usize::try_from(min_len).unwrap_or(usize::MAX).max(1);
+		Self {
+			threshold: min_len.saturating_mul(2),
+			min_len,
+		}
+	}
+
+	pub(crate) fn apply(self, options: &mut Options<'_>) {
+		options.threshold = self.threshold;
+		options.min_len = self.min_len;
+	}
+}
+
+fn cache() -> &'static Cache {
+	static CACHE: OnceLock<Cache> = OnceLock::new();
+	CACHE.get_or_init(Default::default)
+}
+
+/// Average duration of `ROUNDS` calls to `spawn`
+fn measure<S: Fn()>(spawn: S) -> Duration {
+	const ROUNDS: u32 = 64;
+	let start = Instant::now();
+	for _ in 0..ROUNDS {
+		spawn();
+	}
+	start.elapsed() / ROUNDS
+}
+
+/// Rough cost of handing a job over to another thread on `backend`, measured
+/// once per process.
+///
+/// Rayon is measured on its global pool, custom pools are assumed to cost the
+/// same.
+fn spawn_overhead(backend: Backend) -> Duration {
+	#[cfg(feature = "rayon")]
+	static RAYON: OnceLock<Duration> = OnceLock::new();
+	static THREADS: OnceLock<Duration> = OnceLock::new();
+	match backend {
+		#[cfg(feature = "rayon")]
+		Backend::Rayon => {
+			*RAYON.get_or_init(|| {
+				measure(|| {
+					rayon::join(|| black_box(()), || black_box(()));
+				})
+			})
+		},
+		Backend::Threads => {
+			*THREADS.get_or_init(|| {
+				measure(|| {
+					thread::scope(|s| {
+						s.spawn(|| black_box(()));
+					})
+				})
+			})
+		},
+		Backend::Sequential => Duration::ZERO,
+	}
+}
+
+/// Returns the calibration previously learned at `location` for `backend`
+pub(crate) fn lookup(
+	location: &'static Location<'static>,
+	backend: Backend,
+) -> Option<Calibration> {
+	cache()
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.get(&(location, backend))
+		.copied()
+}
+
+/// Runs `probe` on a sample of `sample_len` elements and caches the resulting
+/// calibration for `location` and `backend`
+pub(crate) fn probe<P, O>(
+	location: &'static Location<'static>,
+	backend: Backend,
+	sample_len: usize,
+	probe: P,
+) -> (O, Calibration)
+where
+	P: FnOnce() -> O,
+{
+	let overhead = spawn_overhead(backend);
+	let start = Instant::now();
+	let output = probe();
+	let calibration = Calibration::new(sample_len, start.elapsed(), overhead);
+	cache()
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.insert((location, backend), calibration);
+	(output, calibration)
+}
+
+#[cfg(test)]
+mod tests {
+	mod calibration {
+		use std::time::Duration;
+
+		use super::super::Calibration;
+
+		#[test]
+		fn cost_scaling() {
+			let overhead = Duration::from_micros(10);
+			let cheap = Calibration::new(16, Duration::from_nanos(160), overhead);
+			let expensive = Calibration::new(16, Duration::from_millis(16), overhead);
+			assert_eq!(cheap.min_len, 4000);
+			assert_eq!(cheap.threshold, 8000);
+			assert_eq!(expensive.min_len, 1);
+			assert_eq!(expensive.threshold, 2);
+		}
+	}
+}
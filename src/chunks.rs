@@ -0,0 +1,104 @@
+use core::ops::DerefMut;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use crate::inherit::InheritExt;
+use crate::{FromThreadedIterator, Options};
+
+/// Runs `map` on consecutive chunks of `src`, collecting one output per chunk
+/// and parallelizing when `src` is big enough.
+///
+/// Chunks are `chunk_len` elements long, except possibly the last one. If
+/// `chunk_len` is `None`, it is chosen based on [`THRESHOLD`](crate::THRESHOLD)
+/// and the number of threads.
+///
+/// # Panics
+/// Panics if `chunk_len` is `Some(0)`.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_chunks;
+/// let input = vec![1, 2, 3, 4, 5];
+/// let output: Vec<i32> = threaded_map_chunks(&input, Some(2), |chunk| chunk.iter().sum());
+/// assert_eq!(output, vec![3, 7, 5]);
+/// ```
+pub fn threaded_map_chunks<T, F, U, R>(src: &[T], chunk_len: Option<usize>, map: F) -> R
+where
+	F: Fn(&[T]) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Sync,
+	U: Send,
+{
+	let options = Options::default();
+	let chunk_len = chunk_len.unwrap_or_else(|| options.chunk_len(src.len()));
+	#[cfg(feature = "rayon")]
+	if let Some(options) = options.parallel(src.len()) {
+		return options.collect(src.par_chunks(chunk_len).inherit().map(map));
+	}
+	src.chunks(chunk_len).map(map).collect()
+}
+
+/// A chunked version of [`threaded_mutate`](crate::threaded_mutate), handing
+/// `map` whole blocks of elements at once.
+///
+/// This allows per-chunk setup and lets the compiler vectorize the loop inside
+/// `map`. See [`threaded_map_chunks`] for how chunks are formed.
+///
+/// # Panics
+/// Panics if `chunk_len` is `Some(0)`.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_chunks;
+/// let mut data = vec![1, 2, 3, 4, 5];
+/// threaded_mutate_chunks(&mut data, Some(2), |chunk| chunk.reverse());
+/// assert_eq!(data, vec![2, 1, 4, 3, 5]);
+/// ```
+pub fn threaded_mutate_chunks<S, T, F>(src: &mut S, chunk_len: Option<usize>, map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut [T]) + Send + Sync,
+	T: Send,
+{
+	let options = Options::default();
+	let src = &mut **src;
+	let chunk_len = chunk_len.unwrap_or_else(|| options.chunk_len(src.len()));
+	#[cfg(feature = "rayon")]
+	if let Some(options) = options.parallel(src.len()) {
+		return options.install(|| src.par_chunks_mut(chunk_len).inherit().for_each(map));
+	}
+	src.chunks_mut(chunk_len).for_each(map)
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_map_chunks {
+		use super::super::threaded_map_chunks;
+
+		#[test]
+		fn automatic() {
+			let input: Vec<_> = (0..100_000u64).collect();
+			let sums: Vec<u64> = threaded_map_chunks(&input, None, |chunk| chunk.iter().sum());
+			assert!(sums.len() > 1);
+			assert_eq!(sums.into_iter().sum::<u64>(), 99_999 * 100_000 / 2);
+		}
+	}
+
+	mod threaded_mutate_chunks {
+		use super::super::threaded_mutate_chunks;
+
+		#[test]
+		fn fixed() {
+			let mut data = vec![0; 1000];
+			threaded_mutate_chunks(&mut data, Some(100), |chunk| {
+				for (i, x) in chunk.iter_mut().enumerate() {
+					*x = i;
+				}
+			});
+			let expected: Vec<_> = (0..1000).map(|x| x % 100).collect();
+			assert_eq!(data, expected);
+		}
+	}
+}
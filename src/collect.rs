@@ -0,0 +1,33 @@
+#[cfg(feature = "rayon")]
+use rayon::iter::FromParallelIterator;
+
+/// Collections the output of a threaded computation can be collected into.
+///
+/// Implemented for every [`FromIterator`] that is also rayon's
+/// `FromParallelIterator`. When built without the `rayon` feature, every
+/// [`FromIterator`] qualifies.
+#[cfg(feature = "rayon")]
+pub trait FromThreadedIterator<T: Send>: FromIterator<T> + FromParallelIterator<T> {}
+
+#[cfg(feature = "rayon")]
+impl<C, T: Send> FromThreadedIterator<T> for C where C: FromIterator<T> + FromParallelIterator<T> {}
+
+/// Collections the output of a threaded computation can be collected into.
+///
+/// Implemented for every [`FromIterator`] that is also rayon's
+/// `FromParallelIterator`. When built without the `rayon` feature, every
+/// [`FromIterator`] qualifies.
+#[cfg(not(feature = "rayon"))]
+pub trait FromThreadedIterator<T: Send>: FromIterator<T> {}
+
+#[cfg(not(feature = "rayon"))]
+impl<C: FromIterator<T>, T: Send> FromThreadedIterator<T> for C {}
+
+/// Collections the output of a threaded computation can be appended to.
+///
+/// Implemented for every [`Default`] + [`Extend`]. Parallel outputs are
+/// gathered first and appended on the calling thread, so the collection does
+/// not need to be [`Send`].
+pub trait ThreadedExtend<T: Send>: Default + Extend<T> {}
+
+impl<C: Default + Extend<T>, T: Send> ThreadedExtend<T> for C {}
@@ -0,0 +1,637 @@
+#[cfg(feature = "std")]
+use std::{
+	cell::Cell,
+	env::{self, VarError},
+	error::Error,
+	fmt,
+	marker::PhantomData,
+	num::NonZeroUsize,
+	sync::OnceLock,
+	thread,
+};
+#[cfg(feature = "rayon")]
+use std::{collections::HashMap, sync::Mutex};
+#[cfg(feature = "toml")]
+use std::{
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+
+#[cfg(feature = "rayon")]
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::{backend, Backend, THRESHOLD};
+
+/// Environment variable holding the path of the config file
+#[cfg(feature = "toml")]
+const CONFIG_VAR: &str = "VEMCAP_CONFIG";
+
+/// Settings and the environment variables overriding them
+#[cfg(feature = "std")]
+const VARS: [(&str, &str); 4] = [
+	("threshold", "VEMCAP_THRESHOLD"),
+	("threads", "VEMCAP_THREADS"),
+	("backend", "VEMCAP_BACKEND"),
+	("sequential", "VEMCAP_SEQUENTIAL"),
+];
+
+/// Process-wide defaults for [`Options`](crate::Options).
+///
+/// Loaded once, on first use, from the TOML file pointed to by
+/// `VEMCAP_CONFIG` and from environment variables, which take precedence:
+///
+/// | Setting      | Variable            | Values                                   |
+/// |--------------|---------------------|------------------------------------------|
+/// | `threshold`  | `VEMCAP_THRESHOLD`  | non-negative integer                     |
+/// | `threads`    | `VEMCAP_THREADS`    | positive integer, `0` for every core     |
+/// | `backend`    | `VEMCAP_BACKEND`    | `rayon`, `threads` or `sequential`       |
+/// | `sequential` | `VEMCAP_SEQUENTIAL` | `true` or `1`, `false` or `0`            |
+///
+/// A config file setting every key looks like this:
+/// ```toml
+/// threshold = 4096
+/// threads = 16
+/// backend = "rayon"
+/// sequential = false
+/// ```
+///
+/// The config file is only read when built with the `toml` feature, and
+/// nothing is read without the `std` feature.
+///
+/// `scope` and `override_config` replace it for the current thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Config {
+	/// Default for [`Options::threshold`](crate::Options::threshold)
+	pub threshold: usize,
+	/// Number of threads parallel work is spread over, every core is used if
+	/// `None` or `Some(0)`
+	pub threads: Option<usize>,
+	/// Default for [`Options::backend`](crate::Options::backend), replaced in
+	/// the loaded configuration by
+	/// [`set_default_backend`](crate::set_default_backend)
+	pub backend: Backend,
+	/// Process everything sequentially, regardless of `threshold` and
+	/// `backend`
+	pub sequential: bool,
+}
+
+impl Config {
+	/// Built-in defaults, used for every setting that is not configured
+	pub const DEFAULT: Self = Self {
+		threshold: THRESHOLD,
+		threads: None,
+		backend: Backend::DEFAULT,
+		sequential: false,
+	};
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+#[cfg(feature = "std")]
+impl Config {
+	/// Reads the configuration from the config file and environment variables
+	/// described in [`Config`].
+	///
+	/// [`config`] reads it once and caches the result, skipping invalid
+	/// settings and keeping the valid ones. Calling this directly lets a
+	/// program report configuration errors at startup, the first one is
+	/// returned.
+	pub fn from_env() -> Result<Self, ConfigError> {
+		let mut error = None;
+		let config = Self::load(|e| {
+			error.get_or_insert(e);
+		});
+		error.map_or(Ok(config), Err)
+	}
+
+	/// Reads the configuration like [`from_env`](Self::from_env), passing
+	/// every invalid setting to `report` instead of applying it
+	fn load<E: FnMut(ConfigError)>(mut report: E) -> Self {
+		let mut config = Self::DEFAULT;
+		#[cfg(feature = "toml")]
+		if let Some(path) = env::var_os(CONFIG_VAR) {
+			config.read_file(Path::new(&path), &mut report);
+		}
+		for (key, var) in VARS {
+			let value = match env::var(var) {
+				Ok(value) => value,
+				Err(VarError::NotPresent) => continue,
+				Err(VarError::NotUnicode(value)) => value.to_string_lossy().into_owned(),
+			};
+			if !config.set(key, &value) {
+				report(ConfigError::Invalid {
+					setting: var.into(),
+					value,
+				});
+			}
+		}
+		config
+	}
+
+	/// Parses `value` into the setting named `key`.
+	///
+	/// Returns `false` if there is no such setting or `value` is invalid.
+	fn set(&mut self, key: &str, value: &str) -> bool {
+		let value = value.trim();
+		match key {
+			"threshold" => value.parse().map(|x| self.threshold = x).is_ok(),
+			"threads" => {
+				value
+					.parse()
+					.map(|x| self.threads = (x != 0).then_some(x))
+					.is_ok()
+			},
+			"backend" => parse_backend(value).map(|x| self.backend = x).is_some(),
+			"sequential" => parse_bool(value).map(|x| self.sequential = x).is_some(),
+			_ => false,
+		}
+	}
+
+	#[cfg(feature = "toml")]
+	fn read_file<E: FnMut(ConfigError)>(&mut self, path: &Path, mut report: E) {
+		match fs::read_to_string(path) {
+			Ok(text) => self.set_toml(path, &text, report),
+			Err(e) => report(ConfigError::Io(path.into(), e)),
+		}
+	}
+
+	/// Applies every valid key of the TOML document `text` read from `path`,
+	/// passing the others to `report`
+	#[cfg(feature = "toml")]
+	fn set_toml<E: FnMut(ConfigError)>(&mut self, path: &Path, text: &str, mut report: E) {
+		let table: toml::Table = match text.parse() {
+			Ok(table) => table,
+			Err(e) => return report(ConfigError::Toml(path.into(), e)),
+		};
+		for (key, value) in table {
+			// Only scalars can be valid, other types are reported by name
+			let value = match value {
+				toml::Value::String(x) => x,
+				toml::Value::Integer(x) => x.to_string(),
+				toml::Value::Boolean(x) => x.to_string(),
+				other => other.type_str().into(),
+			};
+			if !self.set(&key, &value) {
+				report(ConfigError::Invalid {
+					setting: format!("`{key}` in {}", path.display()),
+					value,
+				});
+			}
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+fn parse_backend(value: &str) -> Option<Backend> {
+	match value.to_ascii_lowercase().as_str() {
+		#[cfg(feature = "rayon")]
+		"rayon" => Some(Backend::Rayon),
+		"threads" => Some(Backend::Threads),
+		"sequential" => Some(Backend::Sequential),
+		_ => None,
+	}
+}
+
+#[cfg(feature = "std")]
+fn parse_bool(value: &str) -> Option<bool> {
+	match value.to_ascii_lowercase().as_str() {
+		"true" | "1" => Some(true),
+		"false" | "0" => Some(false),
+		_ => None,
+	}
+}
+
+/// Reason the configuration could not be loaded
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ConfigError {
+	/// The config file could not be read
+	#[cfg(feature = "toml")]
+	Io(PathBuf, io::Error),
+	/// The config file is not valid TOML
+	#[cfg(feature = "toml")]
+	Toml(PathBuf, toml::de::Error),
+	/// A setting is not recognized or has an invalid value
+	Invalid {
+		/// Environment variable or config file key holding the value
+		setting: String,
+		/// The offending value
+		value: String,
+	},
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			#[cfg(feature = "toml")]
+			Self::Io(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+			#[cfg(feature = "toml")]
+			Self::Toml(path, e) => write!(f, "failed to parse {}: {e}", path.display()),
+			Self::Invalid { setting, value } => write!(f, "invalid value for {setting}: {value}"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl Error for ConfigError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			#[cfg(feature = "toml")]
+			Self::Io(_, e) => Some(e),
+			#[cfg(feature = "toml")]
+			Self::Toml(_, e) => Some(e),
+			Self::Invalid { .. } => None,
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+	static OVERRIDE: Cell<Option<Config>> = const { Cell::new(None) };
+}
+
+#[cfg(feature = "std")]
+fn loaded() -> &'static Config {
+	static CONFIG: OnceLock<Config> = OnceLock::new();
+	// Errors are left to callers of `Config::from_env` to report
+	CONFIG.get_or_init(|| Config::load(|_| {}))
+}
+
+/// Returns the effective configuration, which is the one passed to the
+/// innermost active [`scope`] or [`override_config`] on this thread, if any.
+///
+/// Invalid settings are ignored in favor of their [`Config::DEFAULT`] value,
+/// call [`Config::from_env`] to report them.
+///
+/// # Example
+/// ```
+/// # use vemcap::{config, Options};
+/// assert_eq!(Options::default().threshold, config().threshold);
+/// ```
+#[cfg(feature = "std")]
+pub fn config() -> Config {
+	current_override().unwrap_or_else(|| with_stored_backend(*loaded()))
+}
+
+/// Returns the effective configuration, which is [`Config::DEFAULT`] without
+/// the `std` feature, apart from the backend set by
+/// [`set_default_backend`](crate::set_default_backend).
+#[cfg(not(feature = "std"))]
+pub fn config() -> Config {
+	with_stored_backend(Config::DEFAULT)
+}
+
+/// Replaces the backend of `config` with the one passed to
+/// [`set_default_backend`](crate::set_default_backend), if any
+fn with_stored_backend(config: Config) -> Config {
+	Config {
+		backend: backend::stored_backend().unwrap_or(config.backend),
+		..config
+	}
+}
+
+/// Restores the previous configuration of the current thread when dropped.
+///
+/// Returned by [`override_config`].
+#[cfg(feature = "std")]
+#[must_use = "the override is removed as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct OverrideGuard {
+	previous: Option<Config>,
+	/// The override lives in a thread local, so it has to be restored on the
+	/// same thread
+	_not_send: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "std")]
+impl Drop for OverrideGuard {
+	fn drop(&mut self) {
+		OVERRIDE.set(self.previous);
+	}
+}
+
+#[cfg(feature = "std")]
+fn set_override(config: Option<Config>) -> OverrideGuard {
+	OverrideGuard {
+		previous: OVERRIDE.replace(config),
+		_not_send: PhantomData,
+	}
+}
+
+/// Makes `config` the configuration of the current thread until the returned
+/// guard is dropped.
+///
+/// Parallel jobs started by any function of this crate inherit the override,
+/// whichever backend or pool runs them, so nested calls made by `map` behave
+/// the same on every thread. Overrides nest, dropping a guard restores the
+/// configuration that was in effect when it was created.
+///
+/// # Example
+/// ```
+/// # use vemcap::{config, override_config, Config};
+/// let _guard = override_config(Config {
+/// 	threshold: 1 << 20,
+/// 	..config()
+/// });
+/// assert_eq!(config().threshold, 1 << 20);
+/// ```
+#[cfg(feature = "std")]
+pub fn override_config(config: Config) -> OverrideGuard {
+	set_override(Some(config))
+}
+
+/// Runs `op` with `config` as the configuration of the current thread.
+///
+/// See [`override_config`] for details.
+///
+/// # Example
+/// ```
+/// # use std::thread;
+/// # use vemcap::{config, scope, threaded_map, Config};
+/// let sequential = Config {
+/// 	sequential: true,
+/// 	..config()
+/// };
+/// let caller = thread::current().id();
+/// let ids: Vec<_> = scope(sequential, || {
+/// 	threaded_map(vec![(); 1000], |()| thread::current().id())
+/// });
+/// assert!(ids.into_iter().all(|id| id == caller));
+/// ```
+#[cfg(feature = "std")]
+pub fn scope<OP, R>(config: Config, op: OP) -> R
+where
+	OP: FnOnce() -> R,
+{
+	let _guard = override_config(config);
+	op()
+}
+
+/// Returns the override active on the current thread
+#[cfg(feature = "std")]
+pub(crate) fn current_override() -> Option<Config> {
+	OVERRIDE.get()
+}
+
+/// Runs `op` with the override captured on another thread by
+/// [`current_override`].
+///
+/// The override is replaced even if `inherited` is `None`, since the current
+/// thread may be running a job stolen from a scope it knows nothing about.
+#[cfg(feature = "std")]
+pub(crate) fn inherit<OP, R>(inherited: Option<Config>, op: OP) -> R
+where
+	OP: FnOnce() -> R,
+{
+	let _guard = set_override(inherited);
+	op()
+}
+
+/// [`Config::threads`] of the effective configuration, `None` if every core is
+/// used
+#[cfg(feature = "std")]
+fn threads() -> Option<usize> {
+	config().threads.filter(|&threads| threads != 0)
+}
+
+/// Number of threads parallel work is spread over
+#[cfg(feature = "std")]
+pub(crate) fn num_threads() -> usize {
+	threads().unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+}
+
+/// Pool of [`Config::threads`] threads, or `None` to use rayon's global pool.
+///
+/// Pools are built on first use and kept for the rest of the process, one per
+/// thread count.
+#[cfg(feature = "rayon")]
+pub(crate) fn pool() -> Option<&'static ThreadPool> {
+	static POOLS: OnceLock<Mutex<HashMap<usize, &'static ThreadPool>>> = OnceLock::new();
+	let threads = threads()?;
+	let mut pools = POOLS
+		.get_or_init(Default::default)
+		.lock()
+		.unwrap_or_else(|e| e.into_inner());
+	if let Some(pool) = pools.get(&threads) {
+		return Some(pool);
+	}
+	let pool = ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.thread_name(|i| format!("vemcap-{i}"))
+		.build()
+		.ok()?;
+	let pool = &*Box::leak(Box::new(pool));
+	pools.insert(threads, pool);
+	Some(pool)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+	mod set {
+		use super::super::Config;
+		use crate::Backend;
+
+		#[test]
+		fn values() {
+			let mut config = Config::DEFAULT;
+			assert!(config.set("threshold", "4096"));
+			assert!(config.set("threads", " 3 "));
+			assert!(config.set("backend", "Sequential"));
+			assert!(config.set("sequential", "1"));
+			assert_eq!(
+				config,
+				Config {
+					threshold: 4096,
+					threads: Some(3),
+					backend: Backend::Sequential,
+					sequential: true,
+				}
+			);
+			assert!(config.set("threads", "0"));
+			assert_eq!(config.threads, None);
+		}
+
+		#[test]
+		fn invalid() {
+			let mut config = Config::DEFAULT;
+			assert!(!config.set("threshold", "-1"));
+			assert!(!config.set("backend", "gpu"));
+			assert!(!config.set("sequential", "yes"));
+			assert!(!config.set("thresold", "64"));
+			assert_eq!(config, Config::DEFAULT);
+		}
+	}
+
+	#[cfg(feature = "toml")]
+	mod set_toml {
+		use std::path::Path;
+
+		use super::super::{Config, ConfigError};
+		use crate::Backend;
+
+		#[test]
+		fn keys() {
+			let mut config = Config::DEFAULT;
+			let text = "threshold = 128\nthreads = 4\nbackend = \"threads\"\n";
+			config.set_toml(Path::new("vemcap.toml"), text, |e| panic!("{e}"));
+			assert_eq!(config.threshold, 128);
+			assert_eq!(config.threads, Some(4));
+			assert_eq!(config.backend, Backend::Threads);
+		}
+
+		#[test]
+		fn unknown_key() {
+			let mut config = Config::DEFAULT;
+			let mut errors = Vec::new();
+			config.set_toml(
+				Path::new("vemcap.toml"),
+				"treads = 4\nthreshold = 128\n",
+				|e| errors.push(e),
+			);
+			assert_eq!(config.threshold, 128);
+			let [error] = &errors[..] else {
+				panic!("expected one error, got {errors:?}");
+			};
+			assert!(matches!(error, ConfigError::Invalid { .. }));
+			assert_eq!(
+				error.to_string(),
+				"invalid value for `treads` in vemcap.toml: 4"
+			);
+		}
+	}
+
+	mod override_config {
+		use std::thread;
+
+		use super::super::{config, override_config, Config};
+		#[cfg(feature = "rayon")]
+		use crate::{threaded_filter_map, threaded_fold, threaded_map_indexed};
+		use crate::{
+			threaded_map,
+			threaded_map_chunks,
+			threaded_map_on,
+			threaded_map_with_options,
+			threaded_mutate_on,
+			Backend,
+			Options,
+			ThreadsExecutor,
+		};
+
+		#[test]
+		fn nesting() {
+			let outer = override_config(Config {
+				threshold: 1,
+				..config()
+			});
+			{
+				let _inner = override_config(Config {
+					threshold: 2,
+					..config()
+				});
+				assert_eq!(config().threshold, 2);
+			}
+			assert_eq!(config().threshold, 1);
+			drop(outer);
+			assert_eq!(config(), Config::from_env().unwrap());
+		}
+
+		#[test]
+		fn inherited_by_workers() {
+			let backends = [
+				#[cfg(feature = "rayon")]
+				Backend::Rayon,
+				Backend::Threads,
+			];
+			for backend in backends {
+				let _guard = override_config(Config {
+					threshold: 0,
+					threads: Some(2),
+					backend,
+					..config()
+				});
+				let output: Vec<_> = threaded_map(vec![(); 1024], |()| config().threshold);
+				assert!(output.into_iter().all(|x| x == 0));
+			}
+		}
+
+		#[test]
+		fn sequential_everywhere() {
+			let _guard = override_config(Config {
+				threshold: 0,
+				sequential: true,
+				..config()
+			});
+			let caller = thread::current().id();
+			let options = Options {
+				backend: Backend::Threads,
+				..Options::default()
+			};
+			let output: Vec<_> =
+				threaded_map_with_options(vec![(); 1024], &options, |()| thread::current().id());
+			assert!(output.into_iter().all(|id| id == caller));
+			let output: Vec<_> = threaded_map_on(&ThreadsExecutor, vec![(); 1024], |()| {
+				thread::current().id()
+			});
+			assert!(output.into_iter().all(|id| id == caller));
+			let mut data = vec![None; 1024];
+			threaded_mutate_on(&ThreadsExecutor, &mut data, |x| {
+				*x = Some(thread::current().id())
+			});
+			assert!(data.into_iter().all(|id| id == Some(caller)));
+			#[cfg(feature = "rayon")]
+			{
+				let output: Vec<_> =
+					threaded_map_indexed(vec![(); 1024], |_, ()| thread::current().id());
+				assert!(output.into_iter().all(|id| id == caller));
+			}
+		}
+
+		#[test]
+		fn zero_threads() {
+			let backends = [
+				#[cfg(feature = "rayon")]
+				Backend::Rayon,
+				Backend::Threads,
+			];
+			for backend in backends {
+				let _guard = override_config(Config {
+					threshold: 1,
+					threads: Some(0),
+					backend,
+					..config()
+				});
+				let output: Vec<_> = threaded_map(vec![1; 1024], |x| x + 1);
+				assert_eq!(output, vec![2; 1024]);
+				let output: Vec<usize> = threaded_map_chunks(&[1; 1024], None, |x| x.len());
+				assert_eq!(output.into_iter().sum::<usize>(), 1024);
+			}
+		}
+
+		#[cfg(feature = "rayon")]
+		#[test]
+		fn inherited_by_other_functions() {
+			let _guard = override_config(Config {
+				threshold: 1,
+				threads: None,
+				..config()
+			});
+			let output: Vec<_> = threaded_map_indexed(vec![(); 1024], |_, ()| config().threshold);
+			assert!(output.into_iter().all(|x| x == 1));
+			let output: Vec<_> = threaded_filter_map(vec![(); 1024], |()| Some(config().threshold));
+			assert!(output.into_iter().all(|x| x == 1));
+			let thresholds = threaded_fold(
+				vec![(); 1024],
+				|| 0,
+				|sum, ()| sum + config().threshold,
+				|a, b| a + b,
+			);
+			assert_eq!(thresholds, 1024);
+		}
+	}
+}
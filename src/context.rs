@@ -0,0 +1,127 @@
+use alloc::vec::Vec;
+#[cfg(feature = "rayon")]
+use core::mem;
+use core::ops::DerefMut;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Contexts bigger than this are unlikely to fit into a core's cache
+#[cfg(feature = "rayon")]
+const CONTEXT_CACHE_SIZE: usize = 256 * 1024;
+
+/// Tunes `options` for work that reads from `ctx`, whose heap allocations are
+/// not counted.
+///
+/// Large contexts are split into one job per thread, so each worker keeps
+/// reading the same part of memory instead of having its cache repopulated by
+/// jobs stolen from other threads.
+#[cfg(feature = "rayon")]
+fn context_options<C: ?Sized>(ctx: &C, len: usize) -> Options<'static> {
+	let mut options = Options::default();
+	if mem::size_of_val(ctx) > CONTEXT_CACHE_SIZE {
+		options.min_len = len.div_ceil(options.num_threads());
+	}
+	options
+}
+
+/// Same as [`threaded_map`](crate::threaded_map), but `map` also receives a
+/// shared context.
+///
+/// The size of `ctx` is taken into account when deciding how finely to split
+/// the work. Only the memory `ctx` occupies directly counts, as measured by
+/// [`size_of_val`](core::mem::size_of_val), so unsized contexts such as slices
+/// and arrays affect splitting but data behind a pointer does not. Pass
+/// `&vec[..]` rather than `&vec` to have the elements of a [`Vec`] counted.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_with;
+/// let names = ["zero", "one", "two"];
+/// let output: Vec<_> = threaded_map_with(&names[..], vec![2, 0], |names, x| names[x]);
+/// assert_eq!(output, vec!["two", "zero"]);
+/// ```
+pub fn threaded_map_with<C, T, F, U, R>(ctx: &C, src: Vec<T>, map: F) -> R
+where
+	C: Sync + ?Sized,
+	F: Fn(&C, T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = context_options(ctx, src.len()).parallel(src.len()) {
+		return options.collect(
+			src.into_par_iter()
+				.with_min_len(options.min_len)
+				.inherit()
+				.map(|x| map(ctx, x)),
+		);
+	}
+	src.into_iter().map(|x| map(ctx, x)).collect()
+}
+
+/// Same as [`threaded_mutate`](crate::threaded_mutate), but `map` also
+/// receives a shared context.
+///
+/// See [`threaded_map_with`] for how the context affects splitting.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_with;
+/// let weights = vec![1, 10, 100];
+/// let mut data = vec![0, 1, 2];
+/// threaded_mutate_with(&weights, &mut data, |weights, x| *x = weights[*x]);
+/// assert_eq!(data, vec![1, 10, 100]);
+/// ```
+pub fn threaded_mutate_with<C, S, T, F>(ctx: &C, src: &mut S, map: F)
+where
+	C: Sync + ?Sized,
+	S: DerefMut<Target = [T]>,
+	F: Fn(&C, &mut T) + Send + Sync,
+	T: Send,
+{
+	let src = &mut **src;
+	#[cfg(feature = "rayon")]
+	if let Some(options) = context_options(ctx, src.len()).parallel(src.len()) {
+		return options.install(|| {
+			src.par_iter_mut()
+				.with_min_len(options.min_len)
+				.inherit()
+				.for_each(|x| map(ctx, x))
+		});
+	}
+	src.iter_mut().for_each(|x| map(ctx, x))
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_map_with {
+		use super::super::threaded_map_with;
+
+		#[test]
+		fn large_table() {
+			let table: Vec<_> = (0..1_000_000u32).rev().collect();
+			let input = (0..1024usize).collect();
+			let expected: Vec<_> = (0..1024).map(|x| 999_999 - x).collect();
+			let output: Vec<_> = threaded_map_with(&table[..], input, |table, x| table[x]);
+			assert_eq!(output, expected);
+		}
+	}
+
+	mod threaded_mutate_with {
+		use super::super::threaded_mutate_with;
+
+		#[test]
+		fn offset() {
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let expected: Vec<_> = (7..1031).collect();
+			threaded_mutate_with(&7, &mut data, |offset, x| *x += offset);
+			assert_eq!(data, expected);
+		}
+	}
+}
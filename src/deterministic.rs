@@ -0,0 +1,237 @@
+use alloc::vec::Vec;
+use core::ops::{Add, Sub};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Number of elements folded sequentially before block results are combined.
+///
+/// Fixed so that the shape of the reduction never depends on the number of
+/// threads or on whether the parallel branch was taken.
+const BLOCK_LEN: usize = 1024;
+
+/// Floating-point types supported by [`threaded_sum_compensated`]
+pub trait Float:
+	Copy + Default + PartialOrd + Send + Add<Output = Self> + Sub<Output = Self>
+{
+	/// Absolute value of `self`
+	fn abs(self) -> Self;
+}
+
+impl Float for f32 {
+	fn abs(self) -> Self {
+		f32::abs(self)
+	}
+}
+
+impl Float for f64 {
+	fn abs(self) -> Self {
+		f64::abs(self)
+	}
+}
+
+/// Running sum with Neumaier's compensation term
+#[derive(Clone, Copy, Default)]
+struct Compensated<F> {
+	sum: F,
+	compensation: F,
+}
+
+impl<F: Float> Compensated<F> {
+	fn add(self, x: F) -> Self {
+		let sum = self.sum + x;
+		let lost = if self.sum.abs() >= x.abs() {
+			(self.sum - sum) + x
+		} else {
+			(x - sum) + self.sum
+		};
+		Self {
+			sum,
+			compensation: self.compensation + lost,
+		}
+	}
+
+	fn merge(self, other: Self) -> Self {
+		let mut merged = self.add(other.sum);
+		merged.compensation = merged.compensation + other.compensation;
+		merged
+	}
+
+	fn total(self) -> F {
+		self.sum + self.compensation
+	}
+}
+
+/// Combines adjacent pairs level by level until a single value remains
+fn pairwise<A, ID, C>(mut level: Vec<A>, init: ID, combine: C) -> A
+where
+	ID: Fn() -> A,
+	C: Fn(A, A) -> A,
+{
+	while level.len() > 1 {
+		let mut next = Vec::with_capacity(level.len().div_ceil(2));
+		let mut values = level.into_iter();
+		while let Some(a) = values.next() {
+			next.push(match values.next() {
+				Some(b) => combine(a, b),
+				None => a,
+			});
+		}
+		level = next;
+	}
+	level.pop().unwrap_or_else(init)
+}
+
+fn fold_blocks_sequential<T, A, ID, F>(src: Vec<T>, init: &ID, fold: &F) -> Vec<A>
+where
+	ID: Fn() -> A,
+	F: Fn(A, T) -> A,
+{
+	let mut src = src.into_iter();
+	let mut blocks = Vec::with_capacity(src.len().div_ceil(BLOCK_LEN));
+	while src.len() > 0 {
+		blocks.push(src.by_ref().take(BLOCK_LEN).fold(init(), fold));
+	}
+	blocks
+}
+
+/// Folds each block of `src` into its own accumulator
+fn fold_blocks<T, A, ID, F>(src: Vec<T>, init: &ID, fold: &F) -> Vec<A>
+where
+	ID: Fn() -> A + Send + Sync,
+	F: Fn(A, T) -> A + Send + Sync,
+	T: Send,
+	A: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.into_par_iter()
+				.fold_chunks(BLOCK_LEN, init, fold)
+				.inherit()
+				.collect()
+		});
+	}
+	fold_blocks_sequential(src, init, fold)
+}
+
+/// Same as [`threaded_fold`](crate::threaded_fold), but the result does not
+/// depend on how the work was split.
+///
+/// `src` is cut into blocks of a fixed length that are folded sequentially,
+/// then the per-block accumulators are merged with `combine` along a fixed
+/// pairwise tree. This gives bit-identical results for non-associative
+/// operations such as floating-point addition regardless of the number of
+/// threads or whether the computation was parallelized at all.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_fold_deterministic;
+/// let input = vec![0.1f32; 10_000];
+/// let sum = threaded_fold_deterministic(input, || 0.0, |a, x| a + x, |a, b| a + b);
+/// assert!((sum - 1000.0).abs() < 0.01);
+/// ```
+pub fn threaded_fold_deterministic<T, A, ID, F, C>(src: Vec<T>, init: ID, fold: F, combine: C) -> A
+where
+	ID: Fn() -> A + Send + Sync,
+	F: Fn(A, T) -> A + Send + Sync,
+	C: Fn(A, A) -> A + Send + Sync,
+	T: Send,
+	A: Send,
+{
+	pairwise(fold_blocks(src, &init, &fold), init, combine)
+}
+
+/// Same as [`threaded_reduce`](crate::threaded_reduce), but the result does
+/// not depend on how the work was split.
+///
+/// See [`threaded_fold_deterministic`] for details.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_reduce_deterministic;
+/// let input: Vec<f64> = (0..10_000).map(|x| 1.0 / x as f64).collect();
+/// let a = threaded_reduce_deterministic(input.clone(), || 0.0, |a, b| a + b);
+/// let b = threaded_reduce_deterministic(input, || 0.0, |a, b| a + b);
+/// assert_eq!(a.to_bits(), b.to_bits());
+/// ```
+pub fn threaded_reduce_deterministic<T, ID, OP>(src: Vec<T>, identity: ID, op: OP) -> T
+where
+	ID: Fn() -> T + Send + Sync,
+	OP: Fn(T, T) -> T + Send + Sync,
+	T: Send,
+{
+	threaded_fold_deterministic(src, &identity, &op, &op)
+}
+
+/// Sums floating-point numbers with Neumaier's compensated summation,
+/// parallelizing when `src` is big enough.
+///
+/// Much less rounding error accumulates than with naive summation, and the
+/// result is as reproducible as that of [`threaded_fold_deterministic`].
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_sum_compensated;
+/// let input = vec![1.0, 1e100, 1.0, -1e100];
+/// assert_eq!(threaded_sum_compensated(input), 2.0);
+/// ```
+pub fn threaded_sum_compensated<F: Float>(src: Vec<F>) -> F {
+	threaded_fold_deterministic(
+		src,
+		Compensated::default,
+		Compensated::add,
+		Compensated::merge,
+	)
+	.total()
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(feature = "rayon")]
+	mod threaded_fold_deterministic {
+		use super::super::{fold_blocks_sequential, pairwise, threaded_fold_deterministic};
+
+		fn input() -> Vec<f32> {
+			(1..100_000).map(|x| 1.0 / x as f32).collect()
+		}
+
+		#[test]
+		fn thread_count_independent() {
+			let sums: Vec<_> = [1, 3, 8]
+				.into_iter()
+				.map(|threads| {
+					let pool = rayon::ThreadPoolBuilder::new()
+						.num_threads(threads)
+						.build()
+						.unwrap();
+					pool.install(|| {
+						threaded_fold_deterministic(input(), || 0.0, |a, x| a + x, |a, b| a + b)
+					})
+				})
+				.collect();
+			let add = |a: f32, b: f32| a + b;
+			let blocks = fold_blocks_sequential(input(), &|| 0.0, &add);
+			let sequential = pairwise(blocks, || 0.0, add);
+			for sum in sums {
+				assert_eq!(sum.to_bits(), sequential.to_bits());
+			}
+		}
+	}
+
+	mod threaded_sum_compensated {
+		use super::super::threaded_sum_compensated;
+
+		#[test]
+		fn precision() {
+			let input = vec![0.1f32; 1_000_000];
+			let naive: f32 = input.iter().sum();
+			let compensated = threaded_sum_compensated(input);
+			assert_eq!(compensated, 100_000.0);
+			assert_ne!(naive, 100_000.0);
+		}
+	}
+}
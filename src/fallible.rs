@@ -0,0 +1,397 @@
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+#[cfg(feature = "std")]
+use std::{
+	any::Any,
+	error::Error,
+	fmt,
+	panic::{self, AssertUnwindSafe},
+};
+
+#[cfg(feature = "rayon")]
+use rayon::{iter::Either, prelude::*};
+
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+use crate::{FromThreadedIterator, ThreadedExtend};
+
+/// Fallible version of [`threaded_map`](crate::threaded_map).
+///
+/// Returns the first error encountered. Once any element fails, remaining
+/// elements are no longer scheduled, although elements already being processed
+/// by other threads will run to completion.
+///
+/// # Example
+/// ```
+/// # use vemcap::try_threaded_map;
+/// let input = vec!["123", "456", "789"];
+/// let output: Result<Vec<_>, _> = try_threaded_map(input, |x| x.parse::<u16>());
+/// assert_eq!(output, Ok(vec![123, 456, 789]));
+///
+/// let input = vec!["123", "abc", "789"];
+/// let output: Result<Vec<_>, _> = try_threaded_map(input, |x| x.parse::<u16>());
+/// assert!(output.is_err());
+/// ```
+pub fn try_threaded_map<T, F, U, E, R>(src: Vec<T>, map: F) -> Result<R, E>
+where
+	F: Fn(T) -> Result<U, E> + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+	E: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.try_collect(src.into_par_iter().inherit().map(map));
+	}
+	src.into_iter().map(map).collect()
+}
+
+/// Runs a fallible `map` on every element of `src`, collecting successful
+/// outputs and failures separately.
+///
+/// Unlike [`try_threaded_map`], every element is processed. Errors are returned
+/// together with the index of the element that caused them, in input order.
+///
+/// # Example
+/// ```
+/// # use vemcap::try_threaded_map_all;
+/// let input = vec!["1", "x", "3", "y"];
+/// let (output, errors): (Vec<_>, _) = try_threaded_map_all(input, |x| x.parse::<u8>());
+/// assert_eq!(output, vec![1, 3]);
+/// assert_eq!(
+/// 	errors.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+/// 	vec![1, 3]
+/// );
+/// ```
+pub fn try_threaded_map_all<T, F, U, E, R>(src: Vec<T>, map: F) -> (R, Vec<(usize, E)>)
+where
+	F: Fn(T) -> Result<U, E> + Send + Sync,
+	R: ThreadedExtend<U>,
+	T: Send,
+	U: Send,
+	E: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		let (outputs, errors): (Vec<_>, _) = options.install(|| {
+			src.into_par_iter()
+				.enumerate()
+				.inherit()
+				.partition_map(|(i, x)| {
+					match map(x) {
+						Ok(x) => Either::Left(x),
+						Err(e) => Either::Right((i, e)),
+					}
+				})
+		});
+		let mut output = R::default();
+		output.extend(outputs);
+		return (output, errors);
+	}
+	let mut output = R::default();
+	let mut errors = Vec::new();
+	for (i, x) in src.into_iter().enumerate() {
+		match map(x) {
+			Ok(x) => output.extend(Some(x)),
+			Err(e) => errors.push((i, e)),
+		}
+	}
+	(output, errors)
+}
+
+/// Fallible version of [`threaded_mutate`](crate::threaded_mutate).
+///
+/// Returns the first error encountered and stops scheduling the remaining
+/// elements.
+///
+/// On failure `src` is left partially transformed. When running sequentially
+/// every element before the failing one has been mutated and none after it
+/// have. When running in parallel an arbitrary subset of the elements may have
+/// been mutated. In both cases the failing element itself keeps whatever
+/// changes `map` made before returning the error. See
+/// [`try_threaded_mutate_all`] if every element has to be processed.
+///
+/// # Example
+/// ```
+/// # use vemcap::try_threaded_mutate;
+/// let mut data = vec![1u8, 2, 3, 4];
+/// let result = try_threaded_mutate(&mut data, |x| {
+/// 	*x = x.checked_mul(100).ok_or("overflow")?;
+/// 	Ok(())
+/// });
+/// assert_eq!(result, Err("overflow"));
+/// assert_eq!(data, vec![100, 200, 3, 4]);
+/// ```
+pub fn try_threaded_mutate<S, T, F, E>(src: &mut S, map: F) -> Result<(), E>
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) -> Result<(), E> + Send + Sync,
+	T: Send,
+	E: Send,
+{
+	let src = &mut **src;
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.par_iter_mut().inherit().try_for_each(map));
+	}
+	src.iter_mut().try_for_each(map)
+}
+
+/// Runs a fallible `map` on every element of `src`, returning all errors
+/// together with the indices of the elements that caused them, in input order.
+///
+/// Every element is processed, so all elements for which `map` succeeded are
+/// mutated even if others failed.
+///
+/// # Example
+/// ```
+/// # use vemcap::try_threaded_mutate_all;
+/// let mut data = vec![1u8, 2, 3, 4];
+/// let errors = try_threaded_mutate_all(&mut data, |x| {
+/// 	*x = x.checked_mul(100).ok_or("overflow")?;
+/// 	Ok(())
+/// });
+/// assert_eq!(errors, vec![(2, "overflow"), (3, "overflow")]);
+/// assert_eq!(data, vec![100, 200, 3, 4]);
+/// ```
+pub fn try_threaded_mutate_all<S, T, F, E>(src: &mut S, map: F) -> Vec<(usize, E)>
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) -> Result<(), E> + Send + Sync,
+	T: Send,
+	E: Send,
+{
+	let src = &mut **src;
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.par_iter_mut()
+				.enumerate()
+				.inherit()
+				.filter_map(|(i, x)| map(x).err().map(|e| (i, e)))
+				.collect()
+		});
+	}
+	src.iter_mut()
+		.enumerate()
+		.filter_map(|(i, x)| map(x).err().map(|e| (i, e)))
+		.collect()
+}
+
+/// Reason a [`threaded_mutate_transactional`] call was rolled back
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum TransactionError<E> {
+	/// `map` returned an error
+	Failed(E),
+	/// `map` panicked, the payload is the one passed to [`panic!`]
+	Panicked(Box<dyn Any + Send>),
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Display> fmt::Display for TransactionError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Failed(e) => write!(f, "transaction failed: {e}"),
+			Self::Panicked(_) => f.write_str("transaction panicked"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl<E: Error + 'static> Error for TransactionError<E> {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Failed(e) => Some(e),
+			Self::Panicked(_) => None,
+		}
+	}
+}
+
+/// All-or-nothing version of [`try_threaded_mutate`].
+///
+/// The contents of `src` are snapshotted before running `map`. If any element
+/// fails or `map` panics, the snapshot is restored and the caller sees `src`
+/// unchanged along with the reason of the failure.
+///
+/// # Example
+/// ```
+/// # use vemcap::{threaded_mutate_transactional, TransactionError};
+/// let mut data = vec![1u8, 2, 3, 4];
+/// let result = threaded_mutate_transactional(&mut data, |x| {
+/// 	*x = x.checked_mul(100).ok_or("overflow")?;
+/// 	Ok(())
+/// });
+/// assert!(matches!(result, Err(TransactionError::Failed("overflow"))));
+/// assert_eq!(data, vec![1, 2, 3, 4]);
+/// ```
+#[cfg(feature = "std")]
+pub fn threaded_mutate_transactional<S, T, F, E>(
+	src: &mut S,
+	map: F,
+) -> Result<(), TransactionError<E>>
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) -> Result<(), E> + Send + Sync,
+	T: Clone + Send,
+	E: Send,
+{
+	let snapshot = src.to_vec();
+	let error = match panic::catch_unwind(AssertUnwindSafe(|| try_threaded_mutate(src, map))) {
+		Ok(Ok(())) => return Ok(()),
+		Ok(Err(e)) => TransactionError::Failed(e),
+		Err(payload) => TransactionError::Panicked(payload),
+	};
+	for (x, original) in src.iter_mut().zip(snapshot) {
+		*x = original;
+	}
+	Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+	mod try_threaded_map {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		use super::super::try_threaded_map;
+
+		#[test]
+		fn short_circuit() {
+			let calls = AtomicUsize::new(0);
+			let input = (0..1_000_000u32).collect();
+			let output: Result<Vec<_>, _> = try_threaded_map(input, |x| {
+				calls.fetch_add(1, Ordering::Relaxed);
+				if x == 0 {
+					Err(x)
+				} else {
+					Ok(x)
+				}
+			});
+			assert_eq!(output, Err(0));
+			assert!(calls.into_inner() < 1_000_000);
+		}
+
+		#[test]
+		fn short_circuit_sequential() {
+			let calls = AtomicUsize::new(0);
+			let input = (0..16u32).collect();
+			let output: Result<Vec<_>, _> = try_threaded_map(input, |x| {
+				calls.fetch_add(1, Ordering::Relaxed);
+				if x == 3 {
+					Err(x)
+				} else {
+					Ok(x)
+				}
+			});
+			assert_eq!(output, Err(3));
+			assert_eq!(calls.into_inner(), 4);
+		}
+	}
+
+	mod try_threaded_map_all {
+		use std::marker::PhantomData;
+
+		use super::super::try_threaded_map_all;
+
+		#[test]
+		fn every_error() {
+			let input = (0..1024u32).collect();
+			let (output, errors): (Vec<_>, _) =
+				try_threaded_map_all(input, |x| if x % 3 == 0 { Err(x) } else { Ok(x) });
+			let expected: Vec<_> = (0..1024).filter(|x| x % 3 != 0).collect();
+			let expected_errors: Vec<_> = (0..1024).step_by(3).map(|x| (x as usize, x)).collect();
+			assert_eq!(output, expected);
+			assert_eq!(errors, expected_errors);
+		}
+
+		#[test]
+		fn non_send_output() {
+			#[derive(Default)]
+			struct Local(Vec<u32>, PhantomData<*const ()>);
+
+			impl Extend<u32> for Local {
+				fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+					self.0.extend(iter);
+				}
+			}
+
+			let input = (0..1024u32).collect();
+			let (output, errors): (Local, _) =
+				try_threaded_map_all(input, |x| if x % 2 == 0 { Err(x) } else { Ok(x) });
+			assert_eq!(output.0, (1..1024).step_by(2).collect::<Vec<_>>());
+			assert_eq!(errors.len(), 512);
+		}
+	}
+
+	mod try_threaded_mutate {
+		use super::super::try_threaded_mutate;
+
+		#[test]
+		fn first_error() {
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let result = try_threaded_mutate(&mut data, |x| {
+				match *x {
+					512 => Err(*x),
+					_ => {
+						*x += 1;
+						Ok(())
+					},
+				}
+			});
+			assert_eq!(result, Err(512));
+			assert_eq!(data[512], 512);
+		}
+	}
+
+	mod try_threaded_mutate_all {
+		use super::super::try_threaded_mutate_all;
+
+		#[test]
+		fn every_error() {
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let errors = try_threaded_mutate_all(&mut data, |x| {
+				if *x % 2 == 1 {
+					return Err(*x);
+				}
+				*x += 1;
+				Ok(())
+			});
+			let expected: Vec<_> = (0..1024).map(|x| x | 1).collect();
+			let expected_errors: Vec<_> = (1..1024).step_by(2).map(|x| (x as usize, x)).collect();
+			assert_eq!(data, expected);
+			assert_eq!(errors, expected_errors);
+		}
+	}
+
+	#[cfg(feature = "std")]
+	mod threaded_mutate_transactional {
+		use super::super::{threaded_mutate_transactional, TransactionError};
+
+		#[test]
+		fn rollback_on_error() {
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let result = threaded_mutate_transactional(&mut data, |x| {
+				*x += 1;
+				if *x == 1000 {
+					return Err(());
+				}
+				Ok(())
+			});
+			assert!(matches!(result, Err(TransactionError::Failed(()))));
+			assert_eq!(data, (0..1024).collect::<Vec<_>>());
+		}
+
+		#[test]
+		fn rollback_on_panic() {
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let result = threaded_mutate_transactional(&mut data, |x| {
+				*x += 1;
+				assert_ne!(*x, 1000);
+				Ok::<_, ()>(())
+			});
+			assert!(matches!(result, Err(TransactionError::Panicked(_))));
+			assert_eq!(data, (0..1024).collect::<Vec<_>>());
+		}
+	}
+}
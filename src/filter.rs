@@ -0,0 +1,86 @@
+use alloc::vec::Vec;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Keeps only the elements of `src` for which `predicate` returns `true`,
+/// parallelizing when `src` is big enough.
+///
+/// The order of the remaining elements is preserved.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_filter;
+/// let input = vec![1, 2, 3, 4, 5];
+/// let output: Vec<_> = threaded_filter(input, |x| x % 2 == 1);
+/// assert_eq!(output, vec![1, 3, 5]);
+/// ```
+pub fn threaded_filter<T, P, R>(src: Vec<T>, predicate: P) -> R
+where
+	P: Fn(&T) -> bool + Send + Sync,
+	R: FromThreadedIterator<T>,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.collect(src.into_par_iter().inherit().filter(predicate));
+	}
+	src.into_iter().filter(predicate).collect()
+}
+
+/// Runs `map` on each element of `src`, keeping only the `Some` results,
+/// parallelizing when `src` is big enough.
+///
+/// The order of the remaining elements is preserved.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_filter_map;
+/// let input = vec!["1", "two", "3"];
+/// let output: Vec<_> = threaded_filter_map(input, |x| x.parse::<u8>().ok());
+/// assert_eq!(output, vec![1, 3]);
+/// ```
+pub fn threaded_filter_map<T, F, U, R>(src: Vec<T>, map: F) -> R
+where
+	F: Fn(T) -> Option<U> + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.collect(src.into_par_iter().inherit().filter_map(map));
+	}
+	src.into_iter().filter_map(map).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_filter {
+		use super::super::threaded_filter;
+
+		#[test]
+		fn evens() {
+			let input = (0..1024u32).collect();
+			let expected: Vec<_> = (0..1024u32).step_by(2).collect();
+			let output: Vec<_> = threaded_filter(input, |x| x % 2 == 0);
+			assert_eq!(output, expected);
+		}
+	}
+
+	mod threaded_filter_map {
+		use super::super::threaded_filter_map;
+
+		#[test]
+		fn checked() {
+			let input = (0..1024u32).collect();
+			let expected: Vec<_> = (0..=255u32).map(|x| x as u8).collect();
+			let output: Vec<_> = threaded_filter_map(input, |x| u8::try_from(x).ok());
+			assert_eq!(output, expected);
+		}
+	}
+}
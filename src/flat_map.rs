@@ -0,0 +1,53 @@
+use alloc::vec::Vec;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Runs `map` on each element of `src` and flattens the results into a single
+/// collection, parallelizing when `src` is big enough.
+///
+/// `map` may produce any number of outputs per element, the order of both
+/// elements and their outputs is preserved. When running in parallel, each job
+/// collects its outputs sequentially and the per-job results are concatenated
+/// once at the end.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_flat_map;
+/// let input = vec!["a b", "", "c"];
+/// let output: Vec<_> = threaded_flat_map(input, |x| x.split_whitespace());
+/// assert_eq!(output, vec!["a", "b", "c"]);
+/// ```
+pub fn threaded_flat_map<T, F, I, R>(src: Vec<T>, map: F) -> R
+where
+	F: Fn(T) -> I + Send + Sync,
+	I: IntoIterator,
+	I::Item: Send,
+	R: FromThreadedIterator<I::Item>,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.collect(src.into_par_iter().inherit().flat_map_iter(map));
+	}
+	src.into_iter().flat_map(map).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_flat_map {
+		use super::super::threaded_flat_map;
+
+		#[test]
+		fn repeat() {
+			let input = (0..256usize).collect();
+			let expected: Vec<_> = (0..256).flat_map(|x| vec![x; x % 4]).collect();
+			let output: Vec<_> = threaded_flat_map(input, |x| vec![x; x % 4]);
+			assert_eq!(output, expected);
+		}
+	}
+}
@@ -0,0 +1,275 @@
+use alloc::vec::Vec;
+use core::{
+	marker::PhantomData,
+	mem::{self, ManuallyDrop},
+	ptr,
+};
+#[cfg(feature = "rayon")]
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::threaded_map;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Raw pointer that may be shared between rayon jobs converting disjoint
+/// chunks
+#[cfg(feature = "rayon")]
+struct SharedPtr<T>(*mut T);
+
+#[cfg(feature = "rayon")]
+impl<T> Clone for SharedPtr<T> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+#[cfg(feature = "rayon")]
+impl<T> Copy for SharedPtr<T> {}
+
+#[cfg(feature = "rayon")]
+unsafe impl<T: Send> Send for SharedPtr<T> {}
+#[cfg(feature = "rayon")]
+unsafe impl<T: Send> Sync for SharedPtr<T> {}
+
+#[cfg(feature = "rayon")]
+impl<T> SharedPtr<T> {
+	/// Going through a method makes closures capture the whole wrapper rather
+	/// than the raw pointer field
+	fn get(self) -> *mut T {
+		self.0
+	}
+}
+
+/// Frees the allocation of a vector without dropping any of its elements
+struct Buffer<T> {
+	ptr: *mut T,
+	capacity: usize,
+}
+
+impl<T> Drop for Buffer<T> {
+	fn drop(&mut self) {
+		unsafe { drop(Vec::from_raw_parts(self.ptr, 0, self.capacity)) }
+	}
+}
+
+/// Cleans up a chunk that is being converted from `T` to `U` if `map` panics.
+///
+/// Elements before `converted` are `U`, the element at `converted` has been
+/// moved into `map`, the rest are still `T`.
+struct Chunk<T, U> {
+	ptr: *mut T,
+	len: usize,
+	converted: usize,
+	_marker: PhantomData<U>,
+}
+
+impl<T, U> Drop for Chunk<T, U> {
+	fn drop(&mut self) {
+		unsafe {
+			ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+				self.ptr.cast::<U>(),
+				self.converted,
+			));
+			ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+				self.ptr.add(self.converted + 1),
+				self.len - self.converted - 1,
+			));
+		}
+	}
+}
+
+/// Converts `len` elements starting at `ptr` from `T` to `U` in place.
+///
+/// If `map` panics the whole chunk is dropped, otherwise it holds `U`s.
+///
+/// # Safety
+/// `ptr` must point to `len` initialized elements that are not accessed
+/// concurrently, and `T` and `U` must have the same layout.
+unsafe fn convert_chunk<T, U, F>(ptr: *mut T, len: usize, map: &F)
+where
+	F: Fn(T) -> U,
+{
+	let mut chunk = Chunk::<T, U> {
+		ptr,
+		len,
+		converted: 0,
+		_marker: PhantomData,
+	};
+	while chunk.converted < len {
+		let src = ptr.add(chunk.converted);
+		let output = map(src.read());
+		src.cast::<U>().write(output);
+		chunk.converted += 1;
+	}
+	mem::forget(chunk);
+}
+
+/// Converts `len` elements starting at `ptr` from `T` to `U` in place,
+/// parallelizing when there are enough of them.
+///
+/// If `map` panics every element is dropped before the panic is propagated.
+///
+/// # Safety
+/// Same as [`convert_chunk`].
+unsafe fn convert<T, U, F>(ptr: *mut T, len: usize, map: &F)
+where
+	F: Fn(T) -> U + Send + Sync,
+	T: Send,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(len) {
+		return convert_parallel(ptr, len, &options, map);
+	}
+	convert_chunk(ptr, len, map)
+}
+
+/// Parallel branch of [`convert`].
+///
+/// # Safety
+/// Same as [`convert_chunk`].
+#[cfg(feature = "rayon")]
+unsafe fn convert_parallel<T, U, F>(ptr: *mut T, len: usize, options: &Options<'_>, map: &F)
+where
+	F: Fn(T) -> U + Send + Sync,
+	T: Send,
+	U: Send,
+{
+	let chunk_len = options.chunk_len(len);
+	let shared = SharedPtr(ptr);
+	let results: Vec<_> = options.install(|| {
+		(0..len.div_ceil(chunk_len))
+			.into_par_iter()
+			.inherit()
+			.map(|i| {
+				let start = i * chunk_len;
+				let chunk = shared.get().add(start);
+				let chunk_len = chunk_len.min(len - start);
+				panic::catch_unwind(AssertUnwindSafe(|| convert_chunk(chunk, chunk_len, map)))
+			})
+			.collect()
+	});
+	if results.iter().all(Result::is_ok) {
+		return;
+	}
+
+	// Chunks that panicked have already been dropped, the rest hold `U`s
+	let mut payload = None;
+	for (i, result) in results.into_iter().enumerate() {
+		match result {
+			Ok(()) => {
+				let start = i * chunk_len;
+				ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+					ptr.add(start).cast::<U>(),
+					chunk_len.min(len - start),
+				));
+			},
+			Err(e) => {
+				payload.get_or_insert(e);
+			},
+		}
+	}
+	if let Some(payload) = payload {
+		panic::resume_unwind(payload);
+	}
+}
+
+/// Same as [`threaded_map`], but reuses the allocation of `src` for the output
+/// when `T` and `U` have the same size and alignment.
+///
+/// If the layouts differ, this falls back to [`threaded_map`] and allocates a
+/// new vector. If `map` panics, every element that was not moved into `map` is
+/// dropped exactly once and the allocation is freed before the panic is
+/// propagated.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_in_place;
+/// let input = vec![1u32, 2, 3];
+/// let ptr = input.as_ptr() as usize;
+/// let output = threaded_map_in_place(input, |x| x as f32 / 2.0);
+/// assert_eq!(output, vec![0.5, 1.0, 1.5]);
+/// assert_eq!(output.as_ptr() as usize, ptr);
+/// ```
+pub fn threaded_map_in_place<T, F, U>(src: Vec<T>, map: F) -> Vec<U>
+where
+	F: Fn(T) -> U + Send + Sync,
+	T: Send,
+	U: Send,
+{
+	if mem::size_of::<T>() != mem::size_of::<U>() || mem::align_of::<T>() != mem::align_of::<U>() {
+		return threaded_map(src, map);
+	}
+
+	let mut src = ManuallyDrop::new(src);
+	let len = src.len();
+	let buffer = Buffer {
+		ptr: src.as_mut_ptr(),
+		capacity: src.capacity(),
+	};
+	unsafe { convert(buffer.ptr, len, &map) };
+
+	let buffer = ManuallyDrop::new(buffer);
+	unsafe { Vec::from_raw_parts(buffer.ptr.cast::<U>(), len, buffer.capacity) }
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_map_in_place {
+		use std::{
+			panic,
+			sync::atomic::{AtomicUsize, Ordering},
+		};
+
+		use super::super::threaded_map_in_place;
+
+		#[test]
+		fn reuses_allocation() {
+			let input: Vec<_> = (0..1024u32).collect();
+			let ptr = input.as_ptr() as usize;
+			let expected: Vec<_> = (0..1024).map(|x| x as f32).collect();
+			let output = threaded_map_in_place(input, |x| x as f32);
+			assert_eq!(output, expected);
+			assert_eq!(output.as_ptr() as usize, ptr);
+		}
+
+		#[test]
+		fn panic_safety() {
+			static INPUTS: AtomicUsize = AtomicUsize::new(0);
+			static OUTPUTS: AtomicUsize = AtomicUsize::new(0);
+			static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+			struct Input(usize);
+			impl Drop for Input {
+				fn drop(&mut self) {
+					INPUTS.fetch_add(1, Ordering::Relaxed);
+				}
+			}
+
+			struct Output(#[allow(dead_code)] usize);
+			impl Drop for Output {
+				fn drop(&mut self) {
+					OUTPUTS.fetch_add(1, Ordering::Relaxed);
+				}
+			}
+
+			let input: Vec<_> = (0..1024).map(Input).collect();
+			let result = panic::catch_unwind(|| {
+				threaded_map_in_place(input, |x| {
+					assert_ne!(x.0, 500);
+					CREATED.fetch_add(1, Ordering::Relaxed);
+					Output(x.0)
+				})
+			});
+			assert!(result.is_err());
+			assert_eq!(INPUTS.load(Ordering::Relaxed), 1024);
+			assert_eq!(
+				OUTPUTS.load(Ordering::Relaxed),
+				CREATED.load(Ordering::Relaxed)
+			);
+		}
+	}
+}
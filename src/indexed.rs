@@ -0,0 +1,97 @@
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Same as [`threaded_map`](crate::threaded_map), but `map` also receives the
+/// index of each element.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_indexed;
+/// let input = vec![10, 20, 30];
+/// let output: Vec<_> = threaded_map_indexed(input, |i, x| i * x);
+/// assert_eq!(output, vec![0, 20, 60]);
+/// ```
+pub fn threaded_map_indexed<T, F, U, R>(src: Vec<T>, map: F) -> R
+where
+	F: Fn(usize, T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.collect(
+			src.into_par_iter()
+				.enumerate()
+				.inherit()
+				.map(|(i, x)| map(i, x)),
+		);
+	}
+	src.into_iter()
+		.enumerate()
+		.map(|(i, x)| map(i, x))
+		.collect()
+}
+
+/// Same as [`threaded_mutate`](crate::threaded_mutate), but `map` also
+/// receives the index of each element.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_indexed;
+/// let mut data = vec![1, 1, 1, 1];
+/// threaded_mutate_indexed(&mut data, |i, x| *x += i);
+/// assert_eq!(data, vec![1, 2, 3, 4]);
+/// ```
+pub fn threaded_mutate_indexed<S, T, F>(src: &mut S, map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(usize, &mut T) + Send + Sync,
+	T: Send,
+{
+	let src = &mut **src;
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.par_iter_mut()
+				.enumerate()
+				.inherit()
+				.for_each(|(i, x)| map(i, x))
+		});
+	}
+	src.iter_mut().enumerate().for_each(|(i, x)| map(i, x))
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_map_indexed {
+		use super::super::threaded_map_indexed;
+
+		#[test]
+		fn weights() {
+			let input = vec![2u32; 1024];
+			let expected: Vec<_> = (0..1024u32).map(|i| i * 2).collect();
+			let output: Vec<_> = threaded_map_indexed(input, |i, x| i as u32 * x);
+			assert_eq!(output, expected);
+		}
+	}
+
+	mod threaded_mutate_indexed {
+		use super::super::threaded_mutate_indexed;
+
+		#[test]
+		fn positions() {
+			let mut data = vec![0; 1024];
+			let expected: Vec<_> = (0..1024).collect();
+			threaded_mutate_indexed(&mut data, |i, x| *x = i);
+			assert_eq!(data, expected);
+		}
+	}
+}
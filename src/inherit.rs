@@ -0,0 +1,175 @@
+use rayon::iter::{
+	plumbing::{Consumer, Folder, ProducerCallback, Reducer, UnindexedConsumer},
+	IndexedParallelIterator,
+	ParallelIterator,
+};
+
+use crate::{config, Config};
+
+/// Adds [`inherit`](InheritExt::inherit) to every parallel iterator
+pub(crate) trait InheritExt: ParallelIterator {
+	/// Makes the configuration override of the current thread visible to
+	/// everything downstream of this iterator, on whichever thread it runs.
+	///
+	/// Only consumers are wrapped, so this has to come after adaptors that
+	/// split the input themselves, such as `enumerate`, `zip` or
+	/// `with_min_len`, and before any closure reading the configuration.
+	fn inherit(self) -> Inherit<Self> {
+		Inherit {
+			base: self,
+			inherited: config::current_override(),
+		}
+	}
+}
+
+impl<I: ParallelIterator> InheritExt for I {}
+
+/// Parallel iterator returned by [`InheritExt::inherit`]
+#[derive(Debug, Clone)]
+pub(crate) struct Inherit<I> {
+	base: I,
+	inherited: Option<Config>,
+}
+
+impl<I: ParallelIterator> ParallelIterator for Inherit<I> {
+	type Item = I::Item;
+
+	fn drive_unindexed<C>(self, consumer: C) -> C::Result
+	where
+		C: UnindexedConsumer<Self::Item>,
+	{
+		self.base
+			.drive_unindexed(InheritConsumer::new(consumer, self.inherited))
+	}
+
+	fn opt_len(&self) -> Option<usize> {
+		self.base.opt_len()
+	}
+}
+
+impl<I: IndexedParallelIterator> IndexedParallelIterator for Inherit<I> {
+	fn drive<C>(self, consumer: C) -> C::Result
+	where
+		C: Consumer<Self::Item>,
+	{
+		self.base
+			.drive(InheritConsumer::new(consumer, self.inherited))
+	}
+
+	fn len(&self) -> usize {
+		self.base.len()
+	}
+
+	fn with_producer<CB>(self, callback: CB) -> CB::Output
+	where
+		CB: ProducerCallback<Self::Item>,
+	{
+		self.base.with_producer(callback)
+	}
+}
+
+/// Consumer running its folders and reducers with the inherited override
+struct InheritConsumer<C> {
+	base: C,
+	inherited: Option<Config>,
+}
+
+impl<C> InheritConsumer<C> {
+	fn new(base: C, inherited: Option<Config>) -> Self {
+		Self { base, inherited }
+	}
+}
+
+impl<T, C: Consumer<T>> Consumer<T> for InheritConsumer<C> {
+	type Folder = InheritFolder<C::Folder>;
+	type Reducer = InheritReducer<C::Reducer>;
+	type Result = C::Result;
+
+	fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+		let (left, right, reducer) = self.base.split_at(index);
+		(
+			Self::new(left, self.inherited),
+			Self::new(right, self.inherited),
+			InheritReducer {
+				base: reducer,
+				inherited: self.inherited,
+			},
+		)
+	}
+
+	fn into_folder(self) -> Self::Folder {
+		let base = self.base;
+		InheritFolder {
+			base: config::inherit(self.inherited, || base.into_folder()),
+			inherited: self.inherited,
+		}
+	}
+
+	fn full(&self) -> bool {
+		self.base.full()
+	}
+}
+
+impl<T, C: UnindexedConsumer<T>> UnindexedConsumer<T> for InheritConsumer<C> {
+	fn split_off_left(&self) -> Self {
+		Self::new(self.base.split_off_left(), self.inherited)
+	}
+
+	fn to_reducer(&self) -> Self::Reducer {
+		InheritReducer {
+			base: self.base.to_reducer(),
+			inherited: self.inherited,
+		}
+	}
+}
+
+/// Folder returned by [`InheritConsumer`]
+struct InheritFolder<F> {
+	base: F,
+	inherited: Option<Config>,
+}
+
+impl<T, F: Folder<T>> Folder<T> for InheritFolder<F> {
+	type Result = F::Result;
+
+	fn consume(self, item: T) -> Self {
+		let base = self.base;
+		Self {
+			base: config::inherit(self.inherited, || base.consume(item)),
+			inherited: self.inherited,
+		}
+	}
+
+	fn consume_iter<I>(self, iter: I) -> Self
+	where
+		I: IntoIterator<Item = T>,
+	{
+		let base = self.base;
+		Self {
+			base: config::inherit(self.inherited, || base.consume_iter(iter)),
+			inherited: self.inherited,
+		}
+	}
+
+	fn complete(self) -> Self::Result {
+		let base = self.base;
+		config::inherit(self.inherited, || base.complete())
+	}
+
+	fn full(&self) -> bool {
+		self.base.full()
+	}
+}
+
+/// Reducer returned by [`InheritConsumer`]
+struct InheritReducer<R> {
+	base: R,
+	inherited: Option<Config>,
+}
+
+impl<T, R: Reducer<T>> Reducer<T> for InheritReducer<R> {
+	fn reduce(self, left: T, right: T) -> T {
+		let base = self.base;
+		config::inherit(self.inherited, || base.reduce(left, right))
+	}
+}
@@ -0,0 +1,138 @@
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Same as [`threaded_map`](crate::threaded_map), but `map` also gets
+/// mutable access to scratch state created by `init`.
+///
+/// `init` is called once when running sequentially. Otherwise the work is
+/// split into one job per thread, so `init` is called at most once per job,
+/// i.e. at most as many times as there are threads in total. A thread may
+/// still run several jobs, so it can see more than one state. Since jobs are
+/// never split further, an idle thread cannot take over part of a slow job,
+/// which hurts when elements take very different times to process.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_init;
+/// let input = vec![3, 1, 2];
+/// let output: Vec<_> = threaded_map_init(input, String::new, |buf, x| {
+/// 	buf.clear();
+/// 	buf.extend(std::iter::repeat('a').take(x));
+/// 	buf.clone()
+/// });
+/// assert_eq!(output, vec!["aaa", "a", "aa"]);
+/// ```
+pub fn threaded_map_init<T, S, I, F, U, R>(src: Vec<T>, init: I, map: F) -> R
+where
+	I: Fn() -> S + Send + Sync,
+	F: Fn(&mut S, T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		let min_len = src.len().div_ceil(options.num_threads());
+		return options.collect(
+			src.into_par_iter()
+				.with_min_len(min_len)
+				.inherit()
+				.map_init(init, map),
+		);
+	}
+	let mut state = init();
+	src.into_iter().map(|x| map(&mut state, x)).collect()
+}
+
+/// Same as [`threaded_mutate`](crate::threaded_mutate), but `map` also gets
+/// mutable access to scratch state created by `init`.
+///
+/// See [`threaded_map_init`] for how often `init` is called.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_init;
+/// let mut data = vec![1, 2, 3];
+/// threaded_mutate_init(&mut data, || 10, |offset, x| *x += *offset);
+/// assert_eq!(data, vec![11, 12, 13]);
+/// ```
+pub fn threaded_mutate_init<S, T, St, I, F>(src: &mut S, init: I, map: F)
+where
+	S: DerefMut<Target = [T]>,
+	I: Fn() -> St + Send + Sync,
+	F: Fn(&mut St, &mut T) + Send + Sync,
+	T: Send,
+{
+	let src = &mut **src;
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		let min_len = src.len().div_ceil(options.num_threads());
+		return options.install(|| {
+			src.par_iter_mut()
+				.with_min_len(min_len)
+				.inherit()
+				.for_each_init(init, map)
+		});
+	}
+	let mut state = init();
+	src.iter_mut().for_each(|x| map(&mut state, x))
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(feature = "std")]
+	mod threaded_map_init {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		use super::super::threaded_map_init;
+		use crate::{scope, Config};
+
+		#[test]
+		fn init_count() {
+			const THREADS: usize = 4;
+			let config = Config {
+				threshold: 1,
+				threads: Some(THREADS),
+				..Config::DEFAULT
+			};
+			let inits = AtomicUsize::new(0);
+			let input = (0..1024u32).collect();
+			let expected: Vec<_> = (0..1024u32).map(|x| x * 2).collect();
+			let output: Vec<_> = scope(config, || {
+				threaded_map_init(
+					input,
+					|| {
+						inits.fetch_add(1, Ordering::Relaxed);
+						2
+					},
+					|factor, x| x * *factor,
+				)
+			});
+			assert_eq!(output, expected);
+			assert!(inits.into_inner() <= THREADS);
+		}
+	}
+
+	mod threaded_mutate_init {
+		use super::super::threaded_mutate_init;
+
+		#[test]
+		fn scratch() {
+			let mut data: Vec<_> = (0..1024).map(|x| x.to_string()).collect();
+			let expected: Vec<_> = (0..1024).map(|x| format!("{x}{x}")).collect();
+			threaded_mutate_init(&mut data, String::new, |buf, x| {
+				buf.clear();
+				buf.push_str(x);
+				x.push_str(buf);
+			});
+			assert_eq!(data, expected);
+		}
+	}
+}
@@ -1,12 +1,102 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(missing_docs)]
+// rustfmt formats doc examples with hard tabs like the rest of the code
+#![allow(clippy::tabs_in_doc_comments)]
 //! A simple PoC crate for splitting computations on large arrays between
 //! threads with [`rayon`]
+//!
+//! # Features
+//! - `rayon` (enabled by default): run parallel work on rayon's thread pools.
+//!   Without it, [`threaded_map`] and [`threaded_mutate`] run on scoped threads
+//!   and every other function runs sequentially.
+//! - `std` (enabled by `rayon`): scoped threads, adaptive [`Options`],
+//!   `threaded_mutate_transactional` and reading [`Config`] from `VEMCAP_*`
+//!   environment variables. Without it the crate is `#![no_std]`, only needs
+//!   `alloc`, and runs everything sequentially.
+//! - `toml` (disabled by default, enables `std`): read [`Config`] from the TOML
+//!   file pointed to by `VEMCAP_CONFIG`.
+//!
+//! [`rayon`]: https://docs.rs/rayon
 
-use std::ops::DerefMut;
+extern crate alloc;
 
-use rayon::prelude::*;
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+#[cfg(feature = "std")]
+use core::panic::Location;
 
-/// After reaching this threshold computations will be parallelized
+#[cfg(feature = "rayon")]
+use rayon::ThreadPool;
+
+pub use crate::{
+	backend::{default_backend, set_default_backend, Backend, Executor, SequentialExecutor},
+	chunks::{threaded_map_chunks, threaded_mutate_chunks},
+	collect::{FromThreadedIterator, ThreadedExtend},
+	config::{config, Config},
+	context::{threaded_map_with, threaded_mutate_with},
+	deterministic::{
+		threaded_fold_deterministic,
+		threaded_reduce_deterministic,
+		threaded_sum_compensated,
+		Float,
+	},
+	fallible::{
+		try_threaded_map,
+		try_threaded_map_all,
+		try_threaded_mutate,
+		try_threaded_mutate_all,
+	},
+	filter::{threaded_filter, threaded_filter_map},
+	flat_map::threaded_flat_map,
+	in_place::threaded_map_in_place,
+	indexed::{threaded_map_indexed, threaded_mutate_indexed},
+	init::{threaded_map_init, threaded_mutate_init},
+	options::Options,
+	reduce::{
+		threaded_fold,
+		threaded_max_by,
+		threaded_min_by,
+		threaded_product,
+		threaded_reduce,
+		threaded_sum,
+	},
+	slice::{threaded_map_extend, threaded_map_into, threaded_map_iter, threaded_map_ref},
+	zip::{threaded_mutate_zip2, threaded_mutate_zip3, threaded_zip_map},
+};
+
+mod backend;
+#[cfg(feature = "std")]
+mod calibrate;
+mod chunks;
+mod collect;
+mod config;
+mod context;
+mod deterministic;
+mod fallible;
+mod filter;
+mod flat_map;
+mod in_place;
+mod indexed;
+#[cfg(feature = "rayon")]
+mod inherit;
+mod init;
+mod options;
+mod reduce;
+mod slice;
+mod zip;
+
+#[cfg(feature = "rayon")]
+pub use crate::backend::RayonExecutor;
+#[cfg(feature = "std")]
+pub use crate::{
+	backend::ThreadsExecutor,
+	config::{override_config, scope, ConfigError, OverrideGuard},
+	fallible::{threaded_mutate_transactional, TransactionError},
+};
+
+/// After reaching this threshold computations will be parallelized.
+///
+/// This is the built-in default of [`Config::threshold`].
 pub const THRESHOLD: usize = 64;
 
 /// Runs `map` on each element of `src`, parallelizing when `src` is big enough.
@@ -21,20 +111,150 @@ pub const THRESHOLD: usize = 64;
 /// let output: Vec<_> = threaded_map(input, |x| x.parse::<u16>().unwrap());
 /// assert_eq!(output, vec![123, 456, 789]);
 /// ```
+#[track_caller]
 pub fn threaded_map<T, F, U, R>(src: Vec<T>, map: F) -> R
 where
 	F: Fn(T) -> U + Send + Sync,
-	R: FromIterator<U> + FromParallelIterator<U>,
+	R: FromThreadedIterator<U>,
 	T: Send,
 	U: Send,
 {
-	if src.len() < THRESHOLD {
-		src.into_iter().map(map).collect()
+	threaded_map_with_options(src, &Options::default(), map)
+}
+
+/// Same as [`threaded_map`], but with custom [`Options`].
+///
+/// # Example
+/// ```
+/// # use vemcap::{threaded_map_with_options, Options};
+/// let options = Options {
+/// 	threshold: 2,
+/// 	..Options::default()
+/// };
+/// let output: Vec<_> = threaded_map_with_options(vec![1, 2, 3], &options, |x| x * 2);
+/// assert_eq!(output, vec![2, 4, 6]);
+/// ```
+///
+/// Letting vemcap decide when to parallelize:
+/// ```
+/// # use vemcap::{threaded_map_with_options, Options};
+/// let options = Options {
+/// 	adaptive: true,
+/// 	..Options::default()
+/// };
+/// let input = (0..1000u64).collect();
+/// let output: Vec<_> = threaded_map_with_options(input, &options, |x| (0..x).sum::<u64>());
+/// assert_eq!(output[999], 498501);
+/// ```
+#[track_caller]
+pub fn threaded_map_with_options<T, F, U, R>(src: Vec<T>, options: &Options<'_>, map: F) -> R
+where
+	F: Fn(T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	map_on(&options.backend, src, options, map)
+}
+
+/// Same as [`threaded_map`], but runs the parallel branch on a custom
+/// [`Executor`].
+///
+/// # Example
+/// ```
+/// # use vemcap::{threaded_map_on, SequentialExecutor};
+/// let input = (0..1000).collect();
+/// let output: Vec<_> = threaded_map_on(&SequentialExecutor, input, |x: u32| x * 2);
+/// assert_eq!(output[999], 1998);
+/// ```
+#[track_caller]
+pub fn threaded_map_on<E, T, F, U, R>(executor: &E, src: Vec<T>, map: F) -> R
+where
+	E: Executor,
+	F: Fn(T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	map_on(executor, src, &Options::default(), map)
+}
+
+#[cfg_attr(not(feature = "std"), allow(unused_mut))]
+#[track_caller]
+fn map_on<E, T, F, U, R>(executor: &E, mut src: Vec<T>, options: &Options<'_>, map: F) -> R
+where
+	E: Executor,
+	F: Fn(T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	if config().sequential {
+		return src.into_iter().map(map).collect();
+	}
+	let mut options = *options;
+	let mut tail = Vec::new();
+	#[cfg(feature = "std")]
+	if !options.calibrate(Location::caller()) {
+		if src.len() <= calibrate::SAMPLE_LEN {
+			return src.into_iter().map(map).collect();
+		}
+		// The probe runs on the last elements so that draining them is free
+		let sample = src.len() - calibrate::SAMPLE_LEN..;
+		let (output, calibration) = calibrate::probe(
+			Location::caller(),
+			options.backend,
+			calibrate::SAMPLE_LEN,
+			|| src.drain(sample).map(&map).collect(),
+		);
+		tail = output;
+		calibration.apply(&mut options);
+	}
+
+	if options.below_threshold(src.len()) {
+		src.into_iter().map(map).chain(tail).collect()
+	} else if tail.is_empty() {
+		executor.map(src, &options, map)
 	} else {
-		src.into_par_iter().map(map).collect()
+		// Only the probing call gets here, so the extra pass is fine
+		let head: Vec<U> = executor.map(src, &options, map);
+		head.into_iter().chain(tail).collect()
 	}
 }
 
+/// Same as [`threaded_map`], but runs on `pool` instead of rayon's global
+/// pool.
+///
+/// Always uses the rayon backend, regardless of
+/// [`default_backend`], unless [`Config::sequential`] is set.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_in_pool;
+/// let pool = rayon::ThreadPoolBuilder::new()
+/// 	.num_threads(2)
+/// 	.build()
+/// 	.unwrap();
+/// let output: Vec<_> = threaded_map_in_pool(&pool, vec![1, 2, 3], |x| x * 2);
+/// assert_eq!(output, vec![2, 4, 6]);
+/// ```
+#[cfg(feature = "rayon")]
+#[track_caller]
+pub fn threaded_map_in_pool<T, F, U, R>(pool: &ThreadPool, src: Vec<T>, map: F) -> R
+where
+	F: Fn(T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Send,
+	U: Send,
+{
+	let options = Options {
+		pool: Some(pool),
+		backend: Backend::Rayon,
+		..Options::default()
+	};
+	threaded_map_with_options(src, &options, map)
+}
+
 /// A more efficient version of [`threaded_map`] for in-place data
 /// transformation.
 ///
@@ -45,19 +265,129 @@ where
 /// threaded_mutate(&mut data, |x| *x *= *x);
 /// assert_eq!(data, vec![1, 4, 9, 16]);
 /// ```
+#[track_caller]
 pub fn threaded_mutate<S, T, F>(src: &mut S, map: F)
 where
 	S: DerefMut<Target = [T]>,
 	F: Fn(&mut T) + Send + Sync,
 	T: Send,
 {
-	if src.len() < THRESHOLD {
+	threaded_mutate_with_options(src, &Options::default(), map)
+}
+
+/// Same as [`threaded_mutate`], but with custom [`Options`].
+///
+/// # Example
+/// ```
+/// # use vemcap::{threaded_mutate_with_options, Options};
+/// let options = Options {
+/// 	min_len: 128,
+/// 	..Options::default()
+/// };
+/// let mut data = vec![1, 2, 3, 4];
+/// threaded_mutate_with_options(&mut data, &options, |x| *x += 1);
+/// assert_eq!(data, vec![2, 3, 4, 5]);
+/// ```
+#[track_caller]
+pub fn threaded_mutate_with_options<S, T, F>(src: &mut S, options: &Options<'_>, map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) + Send + Sync,
+	T: Send,
+{
+	mutate_on(&options.backend, src, options, map)
+}
+
+/// Same as [`threaded_mutate`], but runs the parallel branch on a custom
+/// [`Executor`].
+///
+/// # Example
+/// ```
+/// # use vemcap::{threaded_mutate_on, SequentialExecutor};
+/// let mut data = vec![1, 2, 3, 4];
+/// threaded_mutate_on(&SequentialExecutor, &mut data, |x| *x *= 10);
+/// assert_eq!(data, vec![10, 20, 30, 40]);
+/// ```
+#[track_caller]
+pub fn threaded_mutate_on<E, S, T, F>(executor: &E, src: &mut S, map: F)
+where
+	E: Executor,
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) + Send + Sync,
+	T: Send,
+{
+	mutate_on(executor, src, &Options::default(), map)
+}
+
+#[cfg_attr(not(feature = "std"), allow(unused_mut))]
+#[track_caller]
+fn mutate_on<E, S, T, F>(executor: &E, src: &mut S, options: &Options<'_>, map: F)
+where
+	E: Executor,
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) + Send + Sync,
+	T: Send,
+{
+	let mut src = &mut **src;
+	if config().sequential {
+		return src.iter_mut().for_each(map);
+	}
+	let mut options = *options;
+	#[cfg(feature = "std")]
+	if !options.calibrate(Location::caller()) {
+		if src.len() <= calibrate::SAMPLE_LEN {
+			return src.iter_mut().for_each(map);
+		}
+		let (sample, rest) = src.split_at_mut(calibrate::SAMPLE_LEN);
+		let ((), calibration) = calibrate::probe(
+			Location::caller(),
+			options.backend,
+			calibrate::SAMPLE_LEN,
+			|| sample.iter_mut().for_each(&map),
+		);
+		src = rest;
+		calibration.apply(&mut options);
+	}
+
+	if options.below_threshold(src.len()) {
 		src.iter_mut().for_each(map)
 	} else {
-		src.par_iter_mut().for_each(map)
+		executor.mutate(src, &options, map)
 	}
 }
 
+/// Same as [`threaded_mutate`], but runs on `pool` instead of rayon's global
+/// pool.
+///
+/// See [`threaded_map_in_pool`] for which backend is used.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_in_pool;
+/// let pool = rayon::ThreadPoolBuilder::new()
+/// 	.num_threads(2)
+/// 	.build()
+/// 	.unwrap();
+/// let mut data = vec![1, 2, 3, 4];
+/// threaded_mutate_in_pool(&pool, &mut data, |x| *x -= 1);
+/// assert_eq!(data, vec![0, 1, 2, 3]);
+/// ```
+#[cfg(feature = "rayon")]
+#[track_caller]
+pub fn threaded_mutate_in_pool<S, T, F>(pool: &ThreadPool, src: &mut S, map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T) + Send + Sync,
+	T: Send,
+{
+	let options = Options {
+		pool: Some(pool),
+		backend: Backend::Rayon,
+		..Options::default()
+	};
+	threaded_mutate_with_options(src, &options, map)
+}
+
 #[cfg(test)]
 mod tests {
 	mod threaded_map {
@@ -70,6 +400,78 @@ mod tests {
 			let output: Vec<_> = threaded_map(input, |x| x.pow(2));
 			assert_eq!(output, expected);
 		}
+
+		#[cfg(feature = "rayon")]
+		#[test]
+		fn non_send_output() {
+			use std::marker::PhantomData;
+
+			use rayon::iter::{FromParallelIterator, IntoParallelIterator};
+
+			use crate::threaded_map_in_pool;
+
+			struct Local(Vec<u32>, PhantomData<*const ()>);
+
+			impl FromIterator<u32> for Local {
+				fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+					Self(Vec::from_iter(iter), PhantomData)
+				}
+			}
+
+			impl FromParallelIterator<u32> for Local {
+				fn from_par_iter<I: IntoParallelIterator<Item = u32>>(iter: I) -> Self {
+					Self(Vec::from_par_iter(iter), PhantomData)
+				}
+			}
+
+			let expected: Vec<_> = (1..=1024).collect();
+			let output: Local = threaded_map((0..1024).collect(), |x: u32| x + 1);
+			assert_eq!(output.0, expected);
+
+			let pool = rayon::ThreadPoolBuilder::new()
+				.num_threads(2)
+				.build()
+				.unwrap();
+			let output: Local = threaded_map_in_pool(&pool, (0..1024).collect(), |x: u32| x + 1);
+			assert_eq!(output.0, expected);
+		}
+	}
+
+	mod threaded_map_with_options {
+		use super::super::{threaded_map_with_options, Options};
+
+		#[cfg(feature = "rayon")]
+		#[test]
+		fn custom_pool() {
+			let pool = rayon::ThreadPoolBuilder::new()
+				.num_threads(2)
+				.build()
+				.unwrap();
+			let options = Options {
+				threshold: 0,
+				max_len: 16,
+				pool: Some(&pool),
+				..Options::default()
+			};
+			let input = (0..1024u32).collect();
+			let output: Vec<_> =
+				threaded_map_with_options(input, &options, |_| rayon::current_num_threads());
+			assert!(output.into_iter().all(|x| x == 2));
+		}
+
+		#[test]
+		fn adaptive() {
+			let options = Options {
+				adaptive: true,
+				..Options::default()
+			};
+			let expected: Vec<_> = (0..1024u32).map(|x| x.pow(2)).collect();
+			for _ in 0..2 {
+				let input = (0..1024u32).collect();
+				let output: Vec<_> = threaded_map_with_options(input, &options, |x| x.pow(2));
+				assert_eq!(output, expected);
+			}
+		}
 	}
 
 	mod threaded_mutate {
@@ -84,4 +486,57 @@ mod tests {
 			assert_eq!(data, expected);
 		}
 	}
+
+	mod threaded_mutate_with_options {
+		use super::super::{threaded_mutate_with_options, Options};
+
+		#[test]
+		fn adaptive() {
+			let options = Options {
+				adaptive: true,
+				..Options::default()
+			};
+			let mut data: Vec<_> = (0..1024u32).collect();
+			let expected: Vec<_> = (2..1026).collect();
+			for _ in 0..2 {
+				threaded_mutate_with_options(&mut data, &options, |x| *x += 1);
+			}
+			assert_eq!(data, expected);
+		}
+	}
+
+	#[cfg(feature = "rayon")]
+	mod threaded_mutate_in_pool {
+		use super::super::threaded_mutate_in_pool;
+		use crate::{scope, Backend, Config};
+
+		#[test]
+		fn isolated() {
+			let pool = rayon::ThreadPoolBuilder::new()
+				.num_threads(3)
+				.build()
+				.unwrap();
+			let mut data = vec![0; 1024];
+			threaded_mutate_in_pool(&pool, &mut data, |x| *x = rayon::current_num_threads());
+			assert!(data.into_iter().all(|x| x == 3));
+		}
+
+		#[test]
+		fn other_backend() {
+			let pool = rayon::ThreadPoolBuilder::new()
+				.num_threads(3)
+				.build()
+				.unwrap();
+			let config = Config {
+				threshold: 1,
+				backend: Backend::Threads,
+				..Config::DEFAULT
+			};
+			let mut data = vec![0; 1024];
+			scope(config, || {
+				threaded_mutate_in_pool(&pool, &mut data, |x| *x = rayon::current_num_threads())
+			});
+			assert!(data.into_iter().all(|x| x == 3));
+		}
+	}
 }
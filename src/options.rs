@@ -0,0 +1,200 @@
+#[cfg(feature = "rayon")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::panic::Location;
+
+#[cfg(feature = "rayon")]
+use rayon::{iter::FromParallelIterator, prelude::*, ThreadPool};
+
+#[cfg(feature = "std")]
+use crate::calibrate;
+use crate::{backend, config, Backend};
+
+/// Stand-in for rayon's thread pool when built without the `rayon` feature.
+///
+/// It cannot be constructed, so [`Options::pool`] is always `None`.
+#[cfg(not(feature = "rayon"))]
+#[derive(Debug)]
+pub enum ThreadPool {}
+
+/// Per-call tuning for [`threaded_map_with_options`] and
+/// [`threaded_mutate_with_options`].
+///
+/// Use struct update syntax to override only the parts you care about:
+/// ```
+/// # use vemcap::Options;
+/// let options = Options {
+/// 	threshold: 1024,
+/// 	..Options::default()
+/// };
+/// ```
+///
+/// [`threaded_map_with_options`]: crate::threaded_map_with_options
+/// [`threaded_mutate_with_options`]: crate::threaded_mutate_with_options
+#[derive(Debug, Clone, Copy)]
+pub struct Options<'a> {
+	/// After reaching this many elements computations will be parallelized,
+	/// defaults to [`Config::threshold`](crate::Config::threshold)
+	pub threshold: usize,
+	/// Minimum number of elements processed by a single parallel job
+	pub min_len: usize,
+	/// Maximum number of elements processed by a single parallel job
+	pub max_len: usize,
+	/// Thread pool to run on, rayon's global pool is used if `None`.
+	///
+	/// Defaults to a pool of [`Config::threads`](crate::Config::threads)
+	/// threads if set.
+	pub pool: Option<&'a ThreadPool>,
+	/// Learn `threshold` and `min_len` at runtime instead of using the values
+	/// above.
+	///
+	/// The first call from a given call site times a small sample of elements
+	/// sequentially and estimates whether the remaining work outweighs the cost
+	/// of spawning parallel jobs. The result is cached per call site, so later
+	/// calls from the same place skip the probe.
+	///
+	/// The spawn cost is measured once per backend. For the rayon backend it is
+	/// measured on rayon's global pool, so it only approximates the cost of a
+	/// custom `pool`.
+	///
+	/// Ignored when built without the `std` feature, as there is no clock to
+	/// time the sample with.
+	pub adaptive: bool,
+	/// Executor running the parallel branch, defaults to
+	/// [`default_backend`](crate::default_backend).
+	///
+	/// Ignored if [`Config::sequential`](crate::Config::sequential) is set.
+	pub backend: Backend,
+}
+
+impl Default for Options<'_> {
+	fn default() -> Self {
+		let config = config::config();
+		Self {
+			threshold: config.threshold,
+			min_len: 1,
+			max_len: usize::MAX,
+			#[cfg(feature = "rayon")]
+			pool: config::pool(),
+			#[cfg(not(feature = "rayon"))]
+			pool: None,
+			adaptive: false,
+			backend: backend::default_backend(),
+		}
+	}
+}
+
+impl Options<'_> {
+	/// Resolves the cached calibration for `location` if running adaptively.
+	///
+	/// Returns `false` if the call site still needs to be probed.
+	#[cfg(feature = "std")]
+	pub(crate) fn calibrate(&mut self, location: &'static Location<'static>) -> bool {
+		if !self.adaptive {
+			return true;
+		}
+		match calibrate::lookup(location, self.backend) {
+			Some(calibration) => {
+				calibration.apply(self);
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Whether `len` elements are too few to be worth parallelizing
+	pub(crate) fn below_threshold(&self, len: usize) -> bool {
+		len < self.threshold
+	}
+
+	/// Returns these options if functions that always parallelize with rayon
+	/// should process `len` elements in parallel, `None` if sequentially
+	#[cfg(feature = "rayon")]
+	pub(crate) fn parallel(self, len: usize) -> Option<Self> {
+		let parallel = !config::config().sequential
+			&& !self.below_threshold(len)
+			&& self.backend == Backend::Rayon;
+		parallel.then_some(self)
+	}
+
+	/// Number of threads available to parallel jobs
+	#[cfg(feature = "rayon")]
+	pub(crate) fn num_threads(&self) -> usize {
+		self.pool
+			.map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads)
+	}
+
+	/// Number of threads available to parallel jobs
+	#[cfg(all(feature = "std", not(feature = "rayon")))]
+	pub(crate) fn num_threads(&self) -> usize {
+		config::num_threads()
+	}
+
+	/// Number of threads available to parallel jobs
+	#[cfg(not(feature = "std"))]
+	pub(crate) fn num_threads(&self) -> usize {
+		1
+	}
+
+	/// Picks a chunk length for splitting `len` elements into a few chunks per
+	/// thread, but never below `threshold`
+	pub(crate) fn chunk_len(&self, len: usize) -> usize {
+		len.div_ceil(self.num_threads() * 4)
+			.max(self.threshold)
+			.max(1)
+	}
+
+	/// Runs `op` on `pool` if set, with the override of the current thread
+	#[cfg(feature = "rayon")]
+	pub(crate) fn install<OP, R>(&self, op: OP) -> R
+	where
+		OP: FnOnce() -> R + Send,
+		R: Send,
+	{
+		match self.pool {
+			Some(pool) => {
+				let inherited = config::current_override();
+				pool.install(|| config::inherit(inherited, op))
+			},
+			None => op(),
+		}
+	}
+
+	/// Collects `iter` into `R`, running it on `pool` if set.
+	///
+	/// Results computed on a custom pool go through a [`Vec`] that is
+	/// converted on the calling thread, so `R` does not need to be [`Send`].
+	#[cfg(feature = "rayon")]
+	pub(crate) fn collect<I, R>(&self, iter: I) -> R
+	where
+		I: ParallelIterator,
+		R: FromIterator<I::Item> + FromParallelIterator<I::Item>,
+	{
+		match self.pool {
+			Some(_) => {
+				self.install(|| iter.collect::<Vec<_>>())
+					.into_iter()
+					.collect()
+			},
+			None => iter.collect(),
+		}
+	}
+
+	/// Same as [`collect`](Self::collect), but stops at the first error
+	#[cfg(feature = "rayon")]
+	pub(crate) fn try_collect<I, U, E, R>(&self, iter: I) -> Result<R, E>
+	where
+		I: ParallelIterator<Item = Result<U, E>>,
+		R: FromIterator<U> + FromParallelIterator<U>,
+		U: Send,
+		E: Send,
+	{
+		match self.pool {
+			Some(_) => {
+				self.install(|| iter.collect::<Result<Vec<_>, _>>())
+					.map(|x| x.into_iter().collect())
+			},
+			None => iter.collect(),
+		}
+	}
+}
@@ -0,0 +1,230 @@
+use alloc::vec::Vec;
+use core::{
+	cmp::Ordering,
+	iter::{Product, Sum},
+};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Reduces the elements of `src` into one using `op`, parallelizing when `src`
+/// is big enough.
+///
+/// `identity` may be called any number of times and must produce a value that
+/// leaves other values unchanged when combined with them by `op`, which in turn
+/// must be associative.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_reduce;
+/// let input = vec![1, 2, 3, 4];
+/// assert_eq!(threaded_reduce(input, || 0, |a, b| a + b), 10);
+/// ```
+pub fn threaded_reduce<T, ID, OP>(src: Vec<T>, identity: ID, op: OP) -> T
+where
+	ID: Fn() -> T + Send + Sync,
+	OP: Fn(T, T) -> T + Send + Sync,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.into_par_iter().inherit().reduce(identity, op));
+	}
+	src.into_iter().fold(identity(), op)
+}
+
+/// Folds the elements of `src` into an accumulator, parallelizing when `src`
+/// is big enough.
+///
+/// When running in parallel, each job starts from its own `init()` value and
+/// the per-job accumulators are merged with `combine`.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_fold;
+/// let input = vec!["a", "bc", "def"];
+/// let total = threaded_fold(input, || 0, |acc, x| acc + x.len(), |a, b| a + b);
+/// assert_eq!(total, 6);
+/// ```
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub fn threaded_fold<T, A, ID, F, C>(src: Vec<T>, init: ID, fold: F, combine: C) -> A
+where
+	ID: Fn() -> A + Send + Sync,
+	F: Fn(A, T) -> A + Send + Sync,
+	C: Fn(A, A) -> A + Send + Sync,
+	T: Send,
+	A: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.into_par_iter()
+				.inherit()
+				.fold(&init, fold)
+				.reduce(&init, combine)
+		});
+	}
+	src.into_iter().fold(init(), fold)
+}
+
+/// Sums the elements of `src`, parallelizing when `src` is big enough.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_sum;
+/// let input = (1..=100).collect();
+/// assert_eq!(threaded_sum::<u32, u32>(input), 5050);
+/// ```
+pub fn threaded_sum<T, S>(src: Vec<T>) -> S
+where
+	S: Sum<T> + Sum<S> + Send,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.into_par_iter().inherit().sum());
+	}
+	src.into_iter().sum()
+}
+
+/// Multiplies the elements of `src`, parallelizing when `src` is big enough.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_product;
+/// let input = (1..=5).collect();
+/// assert_eq!(threaded_product::<u32, u32>(input), 120);
+/// ```
+pub fn threaded_product<T, P>(src: Vec<T>) -> P
+where
+	P: Product<T> + Product<P> + Send,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.into_par_iter().inherit().product());
+	}
+	src.into_iter().product()
+}
+
+/// Returns the minimum element of `src` with respect to `compare`,
+/// parallelizing when `src` is big enough.
+///
+/// If several elements are equally minimum, the first one is returned. Returns
+/// `None` if `src` is empty.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_min_by;
+/// let input = vec![3.5, -1.0, 2.0];
+/// assert_eq!(threaded_min_by(input, f64::total_cmp), Some(-1.0));
+/// ```
+pub fn threaded_min_by<T, F>(src: Vec<T>, compare: F) -> Option<T>
+where
+	F: Fn(&T, &T) -> Ordering + Send + Sync,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.into_par_iter().inherit().min_by(compare));
+	}
+	src.into_iter().min_by(compare)
+}
+
+/// Returns the maximum element of `src` with respect to `compare`,
+/// parallelizing when `src` is big enough.
+///
+/// If several elements are equally maximum, the last one is returned. Returns
+/// `None` if `src` is empty.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_max_by;
+/// let input = vec![3.5, -1.0, 2.0];
+/// assert_eq!(threaded_max_by(input, f64::total_cmp), Some(3.5));
+/// ```
+pub fn threaded_max_by<T, F>(src: Vec<T>, compare: F) -> Option<T>
+where
+	F: Fn(&T, &T) -> Ordering + Send + Sync,
+	T: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| src.into_par_iter().inherit().max_by(compare));
+	}
+	src.into_iter().max_by(compare)
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_reduce {
+		use super::super::threaded_reduce;
+
+		#[test]
+		fn concat() {
+			let input: Vec<_> = (0..1024).map(|x| vec![x]).collect();
+			let expected: Vec<_> = (0..1024).collect();
+			let output = threaded_reduce(input, Vec::new, |mut a, b| {
+				a.extend(b);
+				a
+			});
+			assert_eq!(output, expected);
+		}
+	}
+
+	mod threaded_fold {
+		use super::super::threaded_fold;
+
+		#[test]
+		fn count() {
+			let input = (0..1024u32).collect();
+			let output = threaded_fold(input, || 0, |n, x| n + (x % 3 == 0) as usize, |a, b| a + b);
+			assert_eq!(output, 342);
+		}
+	}
+
+	mod threaded_sum {
+		use super::super::threaded_sum;
+
+		#[test]
+		fn integers() {
+			let input = (0..1024u64).collect();
+			assert_eq!(threaded_sum::<_, u64>(input), 1023 * 1024 / 2);
+		}
+	}
+
+	mod threaded_product {
+		use super::super::threaded_product;
+
+		#[test]
+		fn powers() {
+			let input = vec![2u128; 100];
+			assert_eq!(threaded_product::<_, u128>(input), 1 << 100);
+		}
+	}
+
+	mod threaded_min_by {
+		use super::super::threaded_min_by;
+
+		#[test]
+		fn first() {
+			let input = (0..1024u32).map(|x| (x % 7, x)).collect();
+			let output = threaded_min_by(input, |a, b| a.0.cmp(&b.0));
+			assert_eq!(output, Some((0, 0)));
+		}
+	}
+
+	mod threaded_max_by {
+		use super::super::threaded_max_by;
+
+		#[test]
+		fn last() {
+			let input = (0..1024u32).map(|x| (x % 7, x)).collect();
+			let output = threaded_max_by(input, |a, b| a.0.cmp(&b.0));
+			assert_eq!(output, Some((6, 1021)));
+		}
+	}
+}
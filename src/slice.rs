@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Same as [`threaded_map`](crate::threaded_map), but borrows `src` instead of
+/// consuming it.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_ref;
+/// let input = vec!["123".to_owned(), "456".to_owned()];
+/// let output: Vec<_> = threaded_map_ref(&input, |x| x.parse::<u16>().unwrap());
+/// assert_eq!(output, vec![123, 456]);
+/// assert_eq!(input.len(), 2);
+/// ```
+pub fn threaded_map_ref<T, F, U, R>(src: &[T], map: F) -> R
+where
+	F: Fn(&T) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	T: Sync,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.collect(src.par_iter().inherit().map(map));
+	}
+	src.iter().map(map).collect()
+}
+
+/// Same as [`threaded_map`](crate::threaded_map), but accepts any source
+/// with a known length that can be iterated both sequentially and by rayon,
+/// such as ranges, slices or references to vectors.
+///
+/// `src` is cloned to measure its length, which is free for ranges and
+/// references but copies owned collections, so pass those to
+/// [`threaded_map`](crate::threaded_map) instead.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_iter;
+/// let output: Vec<_> = threaded_map_iter(1..4, |x| x * 2);
+/// assert_eq!(output, vec![2, 4, 6]);
+///
+/// let input = vec![1, 2, 3];
+/// let output: Vec<_> = threaded_map_iter(&input, |x| x * 2);
+/// assert_eq!(output, vec![2, 4, 6]);
+/// ```
+#[cfg(feature = "rayon")]
+pub fn threaded_map_iter<I, F, U, R>(src: I, map: F) -> R
+where
+	I: IntoIterator + IntoParallelIterator<Item = <I as IntoIterator>::Item> + Clone,
+	<I as IntoParallelIterator>::Iter: IndexedParallelIterator,
+	F: Fn(<I as IntoIterator>::Item) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	U: Send,
+{
+	let len = src.clone().into_par_iter().len();
+	if let Some(options) = Options::default().parallel(len) {
+		return options.collect(src.into_par_iter().inherit().map(map));
+	}
+	src.into_iter().map(map).collect()
+}
+
+/// Same as [`threaded_map`](crate::threaded_map), but accepts any source
+/// with a known length that can be iterated both sequentially and by rayon,
+/// such as ranges, slices or references to vectors.
+///
+/// Built without the `rayon` feature, `src` is always processed sequentially.
+#[cfg(not(feature = "rayon"))]
+pub fn threaded_map_iter<I, F, U, R>(src: I, map: F) -> R
+where
+	I: IntoIterator + Clone,
+	F: Fn(I::Item) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	U: Send,
+{
+	src.into_iter().map(map).collect()
+}
+
+/// Runs `map` on each element of `src`, writing the results into the
+/// corresponding elements of `out` instead of allocating a new collection.
+///
+/// # Panics
+/// Panics if `src` and `out` have different lengths.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_into;
+/// let input = vec![1, 2, 3];
+/// let mut output = vec![0; 3];
+/// threaded_map_into(&input, &mut output, |x| x * 2);
+/// assert_eq!(output, vec![2, 4, 6]);
+/// ```
+pub fn threaded_map_into<T, F, U>(src: &[T], out: &mut [U], map: F)
+where
+	F: Fn(&T) -> U + Send + Sync,
+	T: Sync,
+	U: Send,
+{
+	assert_eq!(
+		src.len(),
+		out.len(),
+		"source and output buffers must have the same length"
+	);
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			out.par_iter_mut()
+				.zip(src)
+				.inherit()
+				.for_each(|(out, x)| *out = map(x))
+		});
+	}
+	out.iter_mut().zip(src).for_each(|(out, x)| *out = map(x))
+}
+
+/// Runs `map` on each element of `src`, appending the results to `out`.
+///
+/// No allocation takes place if `out` already has enough spare capacity.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_map_extend;
+/// let input = vec![1, 2, 3];
+/// let mut output = Vec::with_capacity(4);
+/// output.push(0);
+/// threaded_map_extend(&input, &mut output, |x| x * 2);
+/// assert_eq!(output, vec![0, 2, 4, 6]);
+/// ```
+pub fn threaded_map_extend<T, F, U>(src: &[T], out: &mut Vec<U>, map: F)
+where
+	F: Fn(&T) -> U + Send + Sync,
+	T: Sync,
+	U: Send,
+{
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| out.par_extend(src.par_iter().inherit().map(map)));
+	}
+	out.extend(src.iter().map(map))
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_map_ref {
+		use super::super::threaded_map_ref;
+
+		#[test]
+		fn lengths() {
+			let input: Vec<_> = (0..1024).map(|x| "a".repeat(x)).collect();
+			let expected: Vec<_> = (0..1024).collect();
+			let output: Vec<_> = threaded_map_ref(&input, String::len);
+			assert_eq!(output, expected);
+		}
+	}
+
+	mod threaded_map_iter {
+		use super::super::threaded_map_iter;
+
+		#[test]
+		fn range() {
+			let expected: Vec<_> = (0..1024u32).map(|x| x.pow(2)).collect();
+			let output: Vec<_> = threaded_map_iter(0..1024u32, |x| x.pow(2));
+			assert_eq!(output, expected);
+		}
+
+		#[test]
+		fn sequential() {
+			let caller = std::thread::current().id();
+			let output: Vec<_> = threaded_map_iter(0..8, |_| std::thread::current().id());
+			assert!(output.into_iter().all(|id| id == caller));
+		}
+	}
+
+	mod threaded_map_into {
+		use super::super::threaded_map_into;
+
+		#[test]
+		fn reuse() {
+			let input: Vec<_> = (0..1024u32).collect();
+			let mut output = vec![0; 1024];
+			for i in 1..3 {
+				threaded_map_into(&input, &mut output, |x| x * i);
+				assert!(output.iter().zip(&input).all(|(y, x)| *y == x * i));
+			}
+		}
+
+		#[test]
+		#[should_panic = "same length"]
+		fn length_mismatch() {
+			threaded_map_into(&[1, 2, 3], &mut [0; 2], |x| *x);
+		}
+	}
+
+	mod threaded_map_extend {
+		use super::super::threaded_map_extend;
+
+		#[test]
+		fn no_realloc() {
+			let input: Vec<_> = (0..1024u32).collect();
+			let mut output = Vec::with_capacity(2048);
+			let ptr = output.as_ptr();
+			threaded_map_extend(&input, &mut output, |x| x + 1);
+			threaded_map_extend(&input, &mut output, |x| x + 2);
+			let expected: Vec<_> = (1..1025).chain(2..1026).collect();
+			assert_eq!(output, expected);
+			assert_eq!(output.as_ptr(), ptr);
+		}
+	}
+}
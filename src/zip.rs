@@ -0,0 +1,179 @@
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::FromThreadedIterator;
+#[cfg(feature = "rayon")]
+use crate::{inherit::InheritExt, Options};
+
+/// Same as [`threaded_map`](crate::threaded_map), but runs `map` on pairs of
+/// elements at the same index in `a` and `b`.
+///
+/// # Panics
+/// Panics if `a` and `b` have different lengths.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_zip_map;
+/// let output: Vec<_> = threaded_zip_map(vec![1, 2, 3], vec![4, 5, 6], |a, b| a * b);
+/// assert_eq!(output, vec![4, 10, 18]);
+/// ```
+pub fn threaded_zip_map<A, B, F, U, R>(a: Vec<A>, b: Vec<B>, map: F) -> R
+where
+	F: Fn(A, B) -> U + Send + Sync,
+	R: FromThreadedIterator<U>,
+	A: Send,
+	B: Send,
+	U: Send,
+{
+	assert_eq!(a.len(), b.len(), "zipped vectors must have the same length");
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(a.len()) {
+		return options.collect(a.into_par_iter().zip(b).inherit().map(|(a, b)| map(a, b)));
+	}
+	a.into_iter().zip(b).map(|(a, b)| map(a, b)).collect()
+}
+
+/// Same as [`threaded_mutate`](crate::threaded_mutate), but `map` also reads
+/// the element at the same index in `a`.
+///
+/// # Panics
+/// Panics if `src` and `a` have different lengths.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_zip2;
+/// let mut positions = vec![0.0, 1.0, 2.0];
+/// let velocities = vec![1.0, -1.0, 0.5];
+/// threaded_mutate_zip2(&mut positions, &velocities, |p, v| *p += v);
+/// assert_eq!(positions, vec![1.0, 0.0, 2.5]);
+/// ```
+pub fn threaded_mutate_zip2<S, T, A, F>(src: &mut S, a: &[A], map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T, &A) + Send + Sync,
+	T: Send,
+	A: Sync,
+{
+	let src = &mut **src;
+	assert_eq!(
+		src.len(),
+		a.len(),
+		"zipped slices must have the same length"
+	);
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.par_iter_mut()
+				.zip(a)
+				.inherit()
+				.for_each(|(x, a)| map(x, a))
+		});
+	}
+	src.iter_mut().zip(a).for_each(|(x, a)| map(x, a))
+}
+
+/// Same as [`threaded_mutate`](crate::threaded_mutate), but `map` also reads
+/// the elements at the same index in `a` and `b`.
+///
+/// # Panics
+/// Panics if `src`, `a` and `b` don't all have the same length.
+///
+/// # Example
+/// ```
+/// # use vemcap::threaded_mutate_zip3;
+/// let mut positions = vec![0.0, 1.0];
+/// let velocities = vec![1.0, -1.0];
+/// let masses = vec![1.0, 2.0];
+/// threaded_mutate_zip3(&mut positions, &velocities, &masses, |p, v, m| *p += v / m);
+/// assert_eq!(positions, vec![1.0, 0.5]);
+/// ```
+pub fn threaded_mutate_zip3<S, T, A, B, F>(src: &mut S, a: &[A], b: &[B], map: F)
+where
+	S: DerefMut<Target = [T]>,
+	F: Fn(&mut T, &A, &B) + Send + Sync,
+	T: Send,
+	A: Sync,
+	B: Sync,
+{
+	let src = &mut **src;
+	assert_eq!(
+		src.len(),
+		a.len(),
+		"zipped slices must have the same length"
+	);
+	assert_eq!(
+		src.len(),
+		b.len(),
+		"zipped slices must have the same length"
+	);
+	#[cfg(feature = "rayon")]
+	if let Some(options) = Options::default().parallel(src.len()) {
+		return options.install(|| {
+			src.par_iter_mut()
+				.zip(a)
+				.zip(b)
+				.inherit()
+				.for_each(|((x, a), b)| map(x, a, b))
+		});
+	}
+	src.iter_mut()
+		.zip(a)
+		.zip(b)
+		.for_each(|((x, a), b)| map(x, a, b))
+}
+
+#[cfg(test)]
+mod tests {
+	mod threaded_zip_map {
+		use super::super::threaded_zip_map;
+
+		#[test]
+		fn pairs() {
+			let a = (0..1024u32).collect();
+			let b = (0..1024u32).rev().collect();
+			let output: Vec<_> = threaded_zip_map(a, b, |a, b| a + b);
+			assert!(output.into_iter().all(|x| x == 1023));
+		}
+
+		#[test]
+		#[should_panic = "same length"]
+		fn length_mismatch() {
+			let _: Vec<_> = threaded_zip_map(vec![1], vec![1, 2], |a, b| a + b);
+		}
+	}
+
+	mod threaded_mutate_zip2 {
+		use super::super::threaded_mutate_zip2;
+
+		#[test]
+		fn step() {
+			let mut positions: Vec<_> = (0..1024i32).collect();
+			let velocities: Vec<_> = (0..1024i32).map(|x| -x).collect();
+			threaded_mutate_zip2(&mut positions, &velocities, |p, v| *p += v);
+			assert!(positions.into_iter().all(|p| p == 0));
+		}
+
+		#[test]
+		#[should_panic = "same length"]
+		fn length_mismatch() {
+			threaded_mutate_zip2(&mut vec![0; 3], &[0; 4], |x, y| *x += y);
+		}
+	}
+
+	mod threaded_mutate_zip3 {
+		use super::super::threaded_mutate_zip3;
+
+		#[test]
+		fn weighted() {
+			let mut data = vec![0; 1024];
+			let a: Vec<_> = (0..1024).collect();
+			let b = vec![3; 1024];
+			let expected: Vec<_> = (0..1024).map(|x| x * 3).collect();
+			threaded_mutate_zip3(&mut data, &a, &b, |x, a, b| *x = a * b);
+			assert_eq!(data, expected);
+		}
+	}
+}